@@ -2,8 +2,11 @@ use std::process::Command;
 
 // Necessary because of this issue: https://github.com/rust-lang/cargo/issues/9641
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    embuild::build::CfgArgs::output_propagated("ESP_IDF")?;
-    embuild::build::LinkArgs::output_propagated("ESP_IDF")?;
+    // ESP-IDF is only built for the ESP32 target, not for the host-side simulator
+    if std::env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("espidf") {
+        embuild::build::CfgArgs::output_propagated("ESP_IDF")?;
+        embuild::build::LinkArgs::output_propagated("ESP_IDF")?;
+    }
 
     let output = Command::new("git").args(["describe", "--always", "--dirty=-d"]).output().unwrap();
     let git_hash = String::from_utf8(output.stdout).unwrap();
@@ -1,28 +1,43 @@
 #![allow(dead_code)]
 
-use super::Effect;
+use super::{Effect, EffectFactory, EffectInfo, FrameContext, ParamSpec, ParamKind};
 use crate::led_control::Color;
 
-use std::time::{Duration, Instant};
+use rand::Rng;
+use std::time::Duration;
 
 pub struct Blink {
-    last_update: Instant
+    interval: Duration,
+    since_update: Duration,
 }
 
 
 impl Blink {
-    pub fn init() -> Self {
+    pub const INFO: EffectInfo = EffectInfo {
+        id: "blink",
+        name: "Blink",
+        params: &[
+            ParamSpec { name: "interval_ms", kind: ParamKind::Int { min: 50, max: 60_000, default: 1000 } },
+        ],
+        factory: EffectFactory::Strip(|params, _| {
+            Ok(Box::new(Blink::init(Duration::from_millis(params.int("interval_ms")? as u64))))
+        }),
+    };
+
+    pub fn init(interval: Duration) -> Self {
         Self {
-            last_update: Instant::now()
+            interval,
+            since_update: Duration::ZERO,
         }
     }
 }
 
 impl Effect for Blink {
-    fn tick(&mut self, segment: &mut crate::led_control::Segment) -> anyhow::Result<()> {
-        if self.last_update.elapsed() > Duration::from_secs(1) {
-            self.last_update = Instant::now();
-            let color = Color::rgb(rand::random(), rand::random(), rand::random());
+    fn tick(&mut self, ctx: &mut FrameContext, segment: &mut crate::led_control::Segment) -> anyhow::Result<()> {
+        self.since_update += ctx.delta;
+        if self.since_update > self.interval {
+            self.since_update = Duration::ZERO;
+            let color = Color::rgb(ctx.rng.gen(), ctx.rng.gen(), ctx.rng.gen());
             for led in segment.leds_mut() {
                 led.set(color);
             }
@@ -1,15 +1,132 @@
-use anyhow::Result;
+use anyhow::{Result, Error};
+use rand::RngCore;
+use serde::{Serialize, Deserialize};
 
-use crate::led_control::Segment;
+use std::time::{Duration, SystemTime};
+
+use crate::led_control::{Canvas, PixelMap, Segment};
 
 mod blink;
+mod plasma;
 mod rainbow;
+mod registry;
+mod ripple;
 mod spookyeyes;
+mod sweep;
 pub use blink::Blink;
+pub use plasma::Plasma;
 pub use rainbow::Rainbow;
+pub use registry::{EffectInfo, EffectFactory, ParamSpec, ParamKind, Params, EFFECTS, find_effect};
+pub use ripple::Ripple;
 pub use spookyeyes::SpookyEyes;
+pub use sweep::Sweep;
 
 
 pub trait Effect {
-    fn tick(&mut self, segment: &mut Segment) -> Result<()>;
-}
\ No newline at end of file
+    fn tick(&mut self, ctx: &mut FrameContext, segment: &mut Segment) -> Result<()>;
+}
+
+/// Effect that draws in two dimensions. On a plain strip it gets a canvas one pixel high
+pub trait Effect2D {
+    fn tick(&mut self, ctx: &mut FrameContext, canvas: &mut Canvas) -> Result<()>;
+}
+
+
+/// Size an effect is built for. Strips are a single row
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Layout {
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Layout {
+    pub fn strip(led_count: usize) -> Self {
+        Self {
+            width: led_count,
+            height: 1,
+        }
+    }
+
+    pub fn led_count(&self) -> usize {
+        self.width * self.height
+    }
+}
+
+
+/// Runs an `Effect2D` on a segment holding the canvas row by row
+struct CanvasEffect {
+    effect: Box<dyn Effect2D + Send>,
+    canvas: Canvas,
+}
+
+impl Effect for CanvasEffect {
+    fn tick(&mut self, ctx: &mut FrameContext, segment: &mut Segment) -> Result<()> {
+        self.effect.tick(ctx, &mut self.canvas)?;
+
+        for (led, color) in segment.leds_mut().iter_mut().zip(self.canvas.pixels()) {
+            led.set(*color);
+        }
+
+        Ok(())
+    }
+}
+
+
+/// Rng handed to effects. ChaCha is used since its output is the same on every platform
+/// and rand version, so a seed replays identically on the host and across devices
+pub type EffectRng = rand_chacha::ChaCha8Rng;
+
+
+/// Time and randomness for a single frame. Effects should use these rather than reading
+/// the system clock or `thread_rng` so they can be driven deterministically
+#[allow(dead_code)] // Not every field is read by the built-in effects
+pub struct FrameContext<'a> {
+    /// Number of frames since the controller started or was reseeded
+    pub frame: u64,
+    /// Time since the previous frame
+    pub delta: Duration,
+    /// Time since the controller started or was reseeded
+    pub elapsed: Duration,
+    /// Wall clock time, for syncing effects between devices
+    pub time: SystemTime,
+    pub rng: &'a mut dyn RngCore,
+}
+
+
+/// Serializable selection of a registered effect and its parameters. Used to select the
+/// running effect at runtime, e.g. `{"name": "rainbow", "deg_per_led": 2}`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EffectConfig {
+    #[serde(rename = "name")]
+    pub id: String,
+    #[serde(flatten)]
+    pub params: Params,
+}
+
+impl EffectConfig {
+    pub fn info(&self) -> Result<&'static EffectInfo> {
+        find_effect(&self.id).ok_or_else(|| Error::msg(format!("Unknown effect '{}'", self.id)))
+    }
+
+    /// Validates the parameters and fills in defaults for anything missing
+    pub fn resolve(self) -> Result<Self> {
+        let params = self.info()?.resolve(&self.params)?;
+        Ok(Self {
+            id: self.id,
+            params,
+        })
+    }
+
+    pub fn build(&self, layout: Layout, map: &PixelMap) -> Result<Box<dyn Effect + Send>> {
+        self.info()?.build(&self.params, layout, map)
+    }
+}
+
+impl Default for EffectConfig {
+    fn default() -> Self {
+        Self {
+            id: SpookyEyes::INFO.id.to_string(),
+            params: SpookyEyes::INFO.resolve(&Params::default()).unwrap_or_default(),
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use super::{Effect2D, EffectFactory, EffectInfo, FrameContext, ParamSpec, ParamKind};
+use crate::led_control::{Canvas, Color};
+
+use std::f32::consts::TAU;
+use std::time::Duration;
+
+/// Sum of moving sine waves across the matrix, mapped to hue
+pub struct Plasma {
+    /// Pixels per wave
+    scale: f32,
+    /// How long one cycle of the pattern takes
+    period: Duration,
+    elapsed: Duration,
+}
+
+
+impl Plasma {
+    pub const INFO: EffectInfo = EffectInfo {
+        id: "plasma",
+        name: "Plasma",
+        params: &[
+            ParamSpec { name: "scale", kind: ParamKind::Int { min: 1, max: 64, default: 8 } },
+            ParamSpec { name: "period_ms", kind: ParamKind::Int { min: 100, max: 60_000, default: 4000 } },
+        ],
+        factory: EffectFactory::Matrix(|params, _, _| {
+            Ok(Box::new(Plasma::init(params.int("scale")? as f32, Duration::from_millis(params.int("period_ms")? as u64))))
+        }),
+    };
+
+    pub fn init(scale: f32, period: Duration) -> Self {
+        Self {
+            scale,
+            period,
+            elapsed: Duration::ZERO,
+        }
+    }
+}
+
+impl Effect2D for Plasma {
+    fn tick(&mut self, ctx: &mut FrameContext, canvas: &mut Canvas) -> anyhow::Result<()> {
+        self.elapsed = Duration::from_secs_f32((self.elapsed + ctx.delta).as_secs_f32() % self.period.as_secs_f32());
+        let phase = self.elapsed.as_secs_f32() / self.period.as_secs_f32() * TAU;
+
+        let (center_x, center_y) = (canvas.width() as f32 / 2.0, canvas.height() as f32 / 2.0);
+        for y in 0..canvas.height() {
+            for x in 0..canvas.width() {
+                let (fx, fy) = (x as f32 / self.scale, y as f32 / self.scale);
+                let distance = ((x as f32 - center_x).hypot(y as f32 - center_y)) / self.scale;
+
+                // Each wave is in -1..1, so the sum is in -3..3
+                let value = (fx * TAU + phase).sin()
+                    + (fy * TAU - phase).sin()
+                    + (distance * TAU + phase).sin();
+
+                let hue = ((value + 3.0) / 6.0 * 255.0) as u8;
+                canvas.set(x, y, Color::hsv(hue, 255, 255));
+            }
+        }
+
+        Ok(())
+    }
+}
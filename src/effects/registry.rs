@@ -0,0 +1,177 @@
+use anyhow::{Result, Error};
+use serde::{Serialize, Deserialize};
+
+use std::collections::BTreeMap;
+
+use super::*;
+
+
+/// All effects that can be constructed at runtime. Adding an effect only requires
+/// adding its `EffectInfo` here.
+pub static EFFECTS: &[EffectInfo] = &[
+    Blink::INFO,
+    Plasma::INFO,
+    Rainbow::INFO,
+    Ripple::INFO,
+    SpookyEyes::INFO,
+    Sweep::INFO,
+];
+
+pub fn find_effect(id: &str) -> Option<&'static EffectInfo> {
+    EFFECTS.iter().find(|info| info.id == id)
+}
+
+
+#[derive(Clone, Copy)]
+pub enum EffectFactory {
+    /// Built with the number of LEDs
+    Strip(fn(&Params, usize) -> Result<Box<dyn Effect + Send>>),
+    /// Built with the width and height
+    Matrix(fn(&Params, usize, usize) -> Result<Box<dyn Effect2D + Send>>),
+    /// Built with the position of each pixel
+    Spatial(fn(&Params, &PixelMap) -> Result<Box<dyn Effect + Send>>),
+}
+
+impl EffectFactory {
+    pub fn is_matrix(&self) -> bool {
+        matches!(self, EffectFactory::Matrix(_))
+    }
+}
+
+/// Describes an effect: its stable id, display name, parameter schema and how to build it
+#[derive(Serialize)]
+pub struct EffectInfo {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub params: &'static [ParamSpec],
+    /// Serialized as whether the effect is 2D
+    #[serde(rename = "matrix", serialize_with = "serialize_factory")]
+    pub factory: EffectFactory,
+}
+
+impl EffectInfo {
+    /// Validates `params` against the schema and fills in defaults for anything missing
+    pub fn resolve(&self, params: &Params) -> Result<Params> {
+        if let Some(name) = params.0.keys().find(|name| !self.params.iter().any(|spec| spec.name == *name)) {
+            return Err(Error::msg(format!("Unknown parameter '{name}' for effect '{}'", self.id)))
+        }
+
+        let mut resolved = Params::default();
+        for spec in self.params {
+            let value = match params.0.get(spec.name) {
+                Some(value) => spec.kind.validate(*value)
+                    .map_err(|e| Error::msg(format!("Parameter '{}': {e}", spec.name)))?,
+                None => spec.kind.default_value(),
+            };
+            resolved.0.insert(spec.name.to_string(), value);
+        }
+
+        Ok(resolved)
+    }
+
+    /// `map` has a point for every pixel of the layout
+    pub fn build(&self, params: &Params, layout: Layout, map: &PixelMap) -> Result<Box<dyn Effect + Send>> {
+        let params = self.resolve(params)?;
+        match self.factory {
+            EffectFactory::Strip(factory) => factory(&params, layout.led_count()),
+            EffectFactory::Spatial(factory) => factory(&params, map),
+            EffectFactory::Matrix(factory) => Ok(Box::new(CanvasEffect {
+                effect: factory(&params, layout.width, layout.height)?,
+                canvas: Canvas::new(layout.width, layout.height),
+            })),
+        }
+    }
+}
+
+fn serialize_factory<S: serde::Serializer>(factory: &EffectFactory, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_bool(factory.is_matrix())
+}
+
+
+#[derive(Debug, Serialize)]
+pub struct ParamSpec {
+    pub name: &'static str,
+    #[serde(flatten)]
+    pub kind: ParamKind,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ParamKind {
+    Int { min: i64, max: i64, default: i64 },
+    Float { min: f32, max: f32, default: f32 },
+    #[allow(dead_code)] // None of the built-in effects take a flag yet
+    Bool { default: bool },
+}
+
+impl ParamKind {
+    fn default_value(&self) -> ParamValue {
+        match *self {
+            ParamKind::Int { default, .. } => ParamValue::Int(default),
+            ParamKind::Float { default, .. } => ParamValue::Float(default),
+            ParamKind::Bool { default } => ParamValue::Bool(default),
+        }
+    }
+
+    fn validate(&self, value: ParamValue) -> Result<ParamValue> {
+        match (*self, value) {
+            (ParamKind::Int { min, max, .. }, ParamValue::Int(val)) => {
+                if val < min || val > max {
+                    return Err(Error::msg(format!("{val} not in range {min}..={max}")))
+                }
+                Ok(value)
+            },
+            (ParamKind::Float { .. }, ParamValue::Int(val)) => {
+                self.validate(ParamValue::Float(val as f32))
+            },
+            (ParamKind::Float { min, max, .. }, ParamValue::Float(val)) => {
+                if !(min..=max).contains(&val) {
+                    return Err(Error::msg(format!("{val} not in range {min}..={max}")))
+                }
+                Ok(value)
+            },
+            (ParamKind::Bool { .. }, ParamValue::Bool(_)) => Ok(value),
+            _ => Err(Error::msg(format!("expected {self:?}, got {value:?}"))),
+        }
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ParamValue {
+    Bool(bool),
+    Int(i64),
+    Float(f32),
+}
+
+
+/// Parameter values keyed by name
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Params(BTreeMap<String, ParamValue>);
+
+impl Params {
+    pub fn int(&self, name: &str) -> Result<i64> {
+        match self.0.get(name) {
+            Some(ParamValue::Int(val)) => Ok(*val),
+            _ => Err(Error::msg(format!("Missing integer parameter '{name}'"))),
+        }
+    }
+
+    pub fn float(&self, name: &str) -> Result<f32> {
+        match self.0.get(name) {
+            Some(ParamValue::Float(val)) => Ok(*val),
+            Some(ParamValue::Int(val)) => Ok(*val as f32),
+            _ => Err(Error::msg(format!("Missing float parameter '{name}'"))),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn bool(&self, name: &str) -> Result<bool> {
+        match self.0.get(name) {
+            Some(ParamValue::Bool(val)) => Ok(*val),
+            _ => Err(Error::msg(format!("Missing bool parameter '{name}'"))),
+        }
+    }
+}
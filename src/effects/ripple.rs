@@ -0,0 +1,61 @@
+use super::{Effect, EffectFactory, EffectInfo, FrameContext, ParamSpec, ParamKind};
+use crate::led_control::{Color, PixelMap, Segment};
+
+use std::f32::consts::TAU;
+use std::time::Duration;
+
+/// Rings of color spreading out from the middle of the installation, following where the
+/// pixels really are
+pub struct Ripple {
+    /// Distance of each pixel from the middle, with the longest side of the map being 1
+    distances: Vec<f32>,
+    /// Rings across the longest side
+    waves: f32,
+    /// How long one ring takes to move out by a wavelength
+    period: Duration,
+    elapsed: Duration,
+}
+
+
+impl Ripple {
+    pub const INFO: EffectInfo = EffectInfo {
+        id: "ripple",
+        name: "Ripple",
+        params: &[
+            ParamSpec { name: "waves", kind: ParamKind::Float { min: 0.1, max: 16.0, default: 2.0 } },
+            ParamSpec { name: "period_ms", kind: ParamKind::Int { min: 100, max: 60_000, default: 2000 } },
+        ],
+        factory: EffectFactory::Spatial(|params, map| {
+            Ok(Box::new(Ripple::init(map, params.float("waves")?, Duration::from_millis(params.int("period_ms")? as u64))))
+        }),
+    };
+
+    pub fn init(map: &PixelMap, waves: f32, period: Duration) -> Self {
+        let map = PixelMap::new(map.normalized());
+        let center = map.center();
+
+        Self {
+            distances: map.points().iter().map(|point| point.distance(&center)).collect(),
+            waves,
+            period,
+            elapsed: Duration::ZERO,
+        }
+    }
+}
+
+impl Effect for Ripple {
+    fn tick(&mut self, ctx: &mut FrameContext, segment: &mut Segment) -> anyhow::Result<()> {
+        self.elapsed = Duration::from_secs_f32((self.elapsed + ctx.delta).as_secs_f32() % self.period.as_secs_f32());
+        let phase = self.elapsed.as_secs_f32() / self.period.as_secs_f32();
+
+        for (led, distance) in segment.leds_mut().iter_mut().zip(&self.distances) {
+            let wave = distance * self.waves - phase;
+            let hue = (wave.rem_euclid(1.0) * 255.0) as u8;
+            // Brightest on the crest of each ring
+            let value = (((wave * TAU).cos() + 1.0) / 2.0 * 255.0) as u8;
+            led.set(Color::hsv(hue, 255, value));
+        }
+
+        Ok(())
+    }
+}
@@ -1,18 +1,17 @@
-use super::Effect;
+use super::{Effect, EffectFactory, EffectInfo, FrameContext, ParamSpec, ParamKind};
 use crate::led_control::Color;
-use crate::led_control::Segment;
+use crate::led_control::{PixelMap, Point, Segment};
 
 use rand::prelude::*;
 use rand::distributions::WeightedIndex;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 pub struct SpookyEyes {
     eye_pairs: Vec<EyePair>,
-    last_update: Instant,
+    points: Vec<Point>,
+    spacing: f32,
 }
 
-const COLOR_CORRECTION: (u8, u8, u8) = (255, 224, 140);
-
 const INIT_ON_TIME: Duration = Duration::from_secs(15);
 const FADETIME: Duration = Duration::from_secs(2);
 const BLINKTIME_MS_MIN:  u64 = 200;
@@ -31,32 +30,50 @@ lazy_static::lazy_static! {
 
 
 impl SpookyEyes {
-    pub fn init(segment_length: usize) -> Self {
-        let mut eye_pairs = vec![];
-
-
-        let mut idx = 0;
-        let mut color_idx = 0;
-        while (idx + 1) < segment_length {
-            eye_pairs.push(EyePair::new((idx, idx+1)));
-            idx += 8; // Good spread with current setup in tree
-            color_idx = (color_idx + 1) % COLOR_WEIGHTS.len();
+    pub const INFO: EffectInfo = EffectInfo {
+        id: "spooky_eyes",
+        name: "Spooky Eyes",
+        params: &[
+            // Minimum distance between eyes, in the units of the pixel map. Without a map
+            // pixels are one apart, so this is in LEDs
+            ParamSpec { name: "spacing", kind: ParamKind::Float { min: 0.01, max: 1000.0, default: 8.0 } },
+        ],
+        factory: EffectFactory::Spatial(|params, map| {
+            Ok(Box::new(SpookyEyes::init(map, params.float("spacing")?)))
+        }),
+    };
+
+    pub fn init(map: &PixelMap, spacing: f32) -> Self {
+        Self {
+            eye_pairs: vec![],
+            points: map.points().to_vec(),
+            spacing,
         }
+    }
 
-        Self {
-            eye_pairs,
-            last_update: Instant::now(),
+    // Eye colors are random, so they are picked on the first tick once an rng is available
+    fn place_eyes(&mut self, rng: &mut dyn RngCore) {
+        // Greedily take each pair of neighbouring pixels that is far enough from every eye
+        // placed so far
+        let mut placed: Vec<Point> = vec![];
+        for idx in 0..self.points.len().saturating_sub(1) {
+            let point = self.points[idx];
+            if placed.iter().all(|other| point.distance(other) >= self.spacing) {
+                placed.push(point);
+                self.eye_pairs.push(EyePair::new((idx, idx+1), rng));
+            }
         }
     }
 }
 
 impl Effect for SpookyEyes {
-    fn tick(&mut self, segment: &mut Segment) -> anyhow::Result<()> {
-        let duration = self.last_update.elapsed();
-        self.last_update = Instant::now();
+    fn tick(&mut self, ctx: &mut FrameContext, segment: &mut Segment) -> anyhow::Result<()> {
+        if self.eye_pairs.is_empty() {
+            self.place_eyes(ctx.rng);
+        }
 
         for eye_pair in self.eye_pairs.iter_mut() {
-            eye_pair.tick(duration, segment)
+            eye_pair.tick(ctx.delta, segment, ctx.rng)
         }
 
         Ok(())
@@ -71,33 +88,28 @@ struct EyePair {
 }
 
 impl EyePair {
-    fn new(indices: (usize, usize)) -> Self {
-        let color = COLOR_WEIGHTS[COLOR_DIST.sample(&mut thread_rng())].0;
+    fn new(indices: (usize, usize), rng: &mut dyn RngCore) -> Self {
+        let color = COLOR_WEIGHTS[COLOR_DIST.sample(rng)].0;
 
-        Self::new_with_color(indices, color)
+        Self::new_with_color(indices, color, rng)
     }
 
-    fn new_with_color(indices: (usize, usize), color: Color) -> Self {
-        let color = Color::rgb(
-            (color.r as f32 * (COLOR_CORRECTION.0 as f32) / 255.0).round() as u8,
-            (color.g as f32 * (COLOR_CORRECTION.1 as f32) / 255.0).round() as u8,
-            (color.b as f32 * (COLOR_CORRECTION.2 as f32) / 255.0).round() as u8);
-
+    fn new_with_color(indices: (usize, usize), color: Color, rng: &mut dyn RngCore) -> Self {
         Self {
             indices,
-            state: EyeState::set_opened_for(INIT_ON_TIME),
+            state: EyeState::set_opened_for(INIT_ON_TIME, rng),
             color,
         }
     }
 
-    fn tick(&mut self, duration: Duration, segment: &mut Segment) {
+    fn tick(&mut self, duration: Duration, segment: &mut Segment, rng: &mut dyn RngCore) {
         let new_color;
 
         match &mut self.state {
             EyeState::Closed(remaining) => {
                 *remaining = remaining.saturating_sub(duration);
                 if remaining.is_zero() {
-                    self.color = COLOR_WEIGHTS[COLOR_DIST.sample(&mut thread_rng())].0;
+                    self.color = COLOR_WEIGHTS[COLOR_DIST.sample(rng)].0;
                     self.state = EyeState::set_opening();
                 }
                 new_color = Color::black();
@@ -108,7 +120,7 @@ impl EyePair {
                 if remaining_on.is_zero() {
                     self.state = EyeState::set_closing();
                 } else if remaining_blink.is_zero() {
-                    let blink_time = rand::thread_rng().gen_range(BLINKTIME_MS_MIN..=BLINKTIME_MS_MAX);
+                    let blink_time = rng.gen_range(BLINKTIME_MS_MIN..=BLINKTIME_MS_MAX);
                     self.state = EyeState::Blinking(*remaining_on, Duration::from_millis(blink_time));
                 }
                 new_color = self.color;
@@ -116,7 +128,7 @@ impl EyePair {
             EyeState::Opening(remaining) => {
                 *remaining = remaining.saturating_sub(duration);
                 if remaining.is_zero() {
-                    self.state = EyeState::set_opened();
+                    self.state = EyeState::set_opened(rng);
                     new_color = self.color;
                 } else {
                     new_color = self.color * (1.0 - remaining.as_secs_f32()/FADETIME.as_secs_f32());
@@ -125,7 +137,7 @@ impl EyePair {
             EyeState::Closing(remaining) => {
                 *remaining = remaining.saturating_sub(duration);
                 if remaining.is_zero() {
-                    self.state = EyeState::set_closed();
+                    self.state = EyeState::set_closed(rng);
                     new_color = Color::black();
                 } else {
                     new_color = self.color * (remaining.as_secs_f32()/FADETIME.as_secs_f32());
@@ -135,7 +147,7 @@ impl EyePair {
                 // Don't decrement remaining_on at this time
                 *remaining_blink = remaining_blink.saturating_sub(duration);
                 if remaining_blink.is_zero() {
-                    self.state = EyeState::set_reopened(*remaining_on);
+                    self.state = EyeState::set_reopened(*remaining_on, rng);
                     new_color = self.color;
                 } else {
                     new_color = Color::black();
@@ -161,19 +173,19 @@ enum EyeState {
 }
 
 impl EyeState {
-    fn set_closed() -> Self {
-        let secs = thread_rng().gen_range(30..=60);
+    fn set_closed(rng: &mut dyn RngCore) -> Self {
+        let secs = rng.gen_range(30..=60);
         EyeState::Closed(Duration::from_secs(secs))
     }
 
-    fn set_opened() -> Self {
-        let secs_on = thread_rng().gen_range(120..=300);
-        let secs_blink = thread_rng().gen_range(2..=30);
+    fn set_opened(rng: &mut dyn RngCore) -> Self {
+        let secs_on = rng.gen_range(120..=300);
+        let secs_blink = rng.gen_range(2..=30);
         EyeState::Opened(Duration::from_secs(secs_on), Duration::from_secs(secs_blink))
     }
 
-    fn set_opened_for(on_duration: Duration) -> Self {
-        let secs_blink = thread_rng().gen_range(2..=30);
+    fn set_opened_for(on_duration: Duration, rng: &mut dyn RngCore) -> Self {
+        let secs_blink = rng.gen_range(2..=30);
         EyeState::Opened(on_duration, Duration::from_secs(secs_blink))
     }
 
@@ -185,8 +197,8 @@ impl EyeState {
         EyeState::Closing(FADETIME)
     }
 
-    fn set_reopened(remaining_on: Duration) -> Self {
-        let secs_blink = thread_rng().gen_range(2..=30);
+    fn set_reopened(remaining_on: Duration, rng: &mut dyn RngCore) -> Self {
+        let secs_blink = rng.gen_range(2..=30);
         EyeState::Opened(remaining_on, Duration::from_secs(secs_blink))
     }
 }
\ No newline at end of file
@@ -0,0 +1,83 @@
+use super::{Effect, EffectFactory, EffectInfo, FrameContext, ParamSpec, ParamKind};
+use crate::led_control::{Color, PixelMap, Segment};
+
+use std::time::Duration;
+
+/// A band of light passing through the installation, like a plane moving through the
+/// pixels' real positions
+pub struct Sweep {
+    /// How far along the direction of travel each pixel is, from 0 to 1
+    positions: Vec<f32>,
+    /// Thickness of the band, as part of the distance it travels
+    width: f32,
+    hue: u8,
+    /// How long one pass across the map takes
+    period: Duration,
+    elapsed: Duration,
+}
+
+
+impl Sweep {
+    pub const INFO: EffectInfo = EffectInfo {
+        id: "sweep",
+        name: "Sweep",
+        params: &[
+            // Direction of travel in degrees, 0 runs along x and 90 along y
+            ParamSpec { name: "angle", kind: ParamKind::Float { min: 0.0, max: 360.0, default: 0.0 } },
+            // Degrees the direction is tipped towards z, for maps with depth
+            ParamSpec { name: "tilt", kind: ParamKind::Float { min: -90.0, max: 90.0, default: 0.0 } },
+            ParamSpec { name: "width", kind: ParamKind::Float { min: 0.01, max: 1.0, default: 0.2 } },
+            ParamSpec { name: "hue", kind: ParamKind::Int { min: 0, max: 255, default: 160 } },
+            ParamSpec { name: "period_ms", kind: ParamKind::Int { min: 100, max: 60_000, default: 3000 } },
+        ],
+        factory: EffectFactory::Spatial(|params, map| {
+            Ok(Box::new(Sweep::init(
+                map,
+                params.float("angle")?,
+                params.float("tilt")?,
+                params.float("width")?,
+                params.int("hue")? as u8,
+                Duration::from_millis(params.int("period_ms")? as u64),
+            )))
+        }),
+    };
+
+    pub fn init(map: &PixelMap, angle: f32, tilt: f32, width: f32, hue: u8, period: Duration) -> Self {
+        let (angle, tilt) = (angle.to_radians(), tilt.to_radians());
+        let (dx, dy, dz) = (angle.cos() * tilt.cos(), angle.sin() * tilt.cos(), tilt.sin());
+
+        // Distance along the direction, then stretched so the first pixel the band reaches is
+        // at 0 and the last at 1
+        let distances = map.points().iter()
+            .map(|point| point.x * dx + point.y * dy + point.z * dz)
+            .collect::<Vec<_>>();
+        let min = distances.iter().copied().fold(f32::INFINITY, f32::min);
+        let max = distances.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let scale = if max > min { 1.0 / (max - min) } else { 1.0 };
+
+        Self {
+            positions: distances.iter().map(|distance| (distance - min) * scale).collect(),
+            width,
+            hue,
+            period,
+            elapsed: Duration::ZERO,
+        }
+    }
+}
+
+impl Effect for Sweep {
+    fn tick(&mut self, ctx: &mut FrameContext, segment: &mut Segment) -> anyhow::Result<()> {
+        self.elapsed = Duration::from_secs_f32((self.elapsed + ctx.delta).as_secs_f32() % self.period.as_secs_f32());
+
+        // Starts and ends with the band just off the map
+        let progress = self.elapsed.as_secs_f32() / self.period.as_secs_f32();
+        let plane = -self.width + progress * (1.0 + 2.0 * self.width);
+
+        for (led, position) in segment.leds_mut().iter_mut().zip(&self.positions) {
+            let falloff = (1.0 - (position - plane).abs() / self.width).max(0.0);
+            led.set(Color::hsv(self.hue, 255, (falloff * 255.0) as u8));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+use super::output::Output;
+use super::segment::Segment;
+use super::chipset::StripConfig;
+
+use anyhow::Result;
+
+use std::sync::{Arc, Mutex};
+
+
+/// Records the byte stream each frame would be sent as instead of driving a strip, so
+/// encodings can be checked without hardware. Clones share the same recording
+#[derive(Clone)]
+pub struct CaptureOutput {
+    strip: StripConfig,
+    frames: Arc<Mutex<Vec<Vec<u8>>>>,
+}
+
+
+impl CaptureOutput {
+    pub fn new(strip: StripConfig) -> Self {
+        Self {
+            strip,
+            frames: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    /// Takes the frames recorded so far
+    pub fn take_frames(&self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut *self.frames.lock().expect("Failed to unlock captured frames mutex"))
+    }
+}
+
+
+impl Output for CaptureOutput {
+    fn write(&mut self, frame: &Segment) -> Result<()> {
+        let data = self.strip.encode_frame(frame);
+        self.frames.lock().expect("Failed to unlock captured frames mutex").push(data);
+
+        Ok(())
+    }
+
+    fn set_strip(&mut self, strip: StripConfig) -> Result<()> {
+        self.strip = strip;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::led_control::{Chipset, Color};
+
+    fn capture(strip: StripConfig, colors: &[Color]) -> Vec<u8> {
+        let mut frame = Segment::new(colors.len());
+        for (led, color) in frame.leds_mut().iter_mut().zip(colors) {
+            led.set(*color);
+        }
+
+        let mut output = CaptureOutput::new(strip);
+        output.write(&frame).unwrap();
+        let mut frames = output.take_frames();
+        assert_eq!(frames.len(), 1);
+        frames.remove(0)
+    }
+
+    fn strip(chipset: Chipset) -> StripConfig {
+        StripConfig { chipset, ..Default::default() }
+    }
+
+    #[test]
+    fn apa102_has_start_and_end_frames() {
+        let data = capture(strip(Chipset::Apa102), &[Color::rgb(1, 2, 3), Color::rgb(4, 5, 6)]);
+        assert_eq!(data, [
+            0, 0, 0, 0,
+            0xff, 3, 2, 1,
+            0xff, 6, 5, 4,
+            0, // Half a clock per pixel, rounded up to a byte per 16
+        ]);
+    }
+
+    #[test]
+    fn apa102_end_frame_covers_the_whole_strip() {
+        let data = capture(strip(Chipset::Apa102), &[Color::black(); 40]);
+        assert_eq!(data.len(), 4 + 40 * 4 + 3);
+        assert!(data[4 + 40 * 4..].iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn sk9822_latches_with_a_reset_frame() {
+        let config = StripConfig { global_brightness: 7, ..strip(Chipset::Sk9822) };
+        let data = capture(config, &[Color::rgb(1, 2, 3)]);
+        assert_eq!(data, [
+            0, 0, 0, 0,
+            0xe7, 3, 2, 1,
+            0, 0, 0, 0,
+            0,
+        ]);
+    }
+
+    #[test]
+    fn ws2801_is_bare_pixels() {
+        // Latches once the clock has been idle for 500us, which the gap between frames gives
+        // it, so nothing is sent around the pixels
+        let strip = strip(Chipset::Ws2801);
+        let data = capture(strip, &[Color::rgb(1, 2, 3), Color::rgb(4, 5, 6)]);
+        assert_eq!(data, [1, 2, 3, 4, 5, 6]);
+
+        let mut output = CaptureOutput::new(strip);
+        let frame = Segment::new(2);
+        output.write(&frame).unwrap();
+        output.write(&frame).unwrap();
+        assert_eq!(output.take_frames(), [[0; 6], [0; 6]]);
+    }
+}
@@ -0,0 +1,265 @@
+use super::color::{Color, WhiteMode};
+use super::segment::Segment;
+
+use anyhow::{Result, Error};
+use serde::{Serialize, Deserialize};
+use std::time::Duration;
+
+
+const DEFAULT_LED_COUNT: usize = 150;
+/// ESP32 pins that can drive a strip. 1 and 3 are the serial console, 6 to 11 run the
+/// flash and 34 to 39 are inputs only
+const OUTPUT_PINS: [u8; 20] = [0, 2, 4, 5, 12, 13, 14, 15, 16, 17, 18, 19, 21, 22, 23, 25, 26, 27, 32, 33];
+
+
+/// Order the color channels are sent in
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorOrder {
+    Rgb,
+    Rbg,
+    Grb,
+    Gbr,
+    Brg,
+    Bgr,
+}
+
+impl ColorOrder {
+    pub fn arrange(&self, color: Color) -> [u8; 3] {
+        let Color { r, g, b, .. } = color;
+        match self {
+            ColorOrder::Rgb => [r, g, b],
+            ColorOrder::Rbg => [r, b, g],
+            ColorOrder::Grb => [g, r, b],
+            ColorOrder::Gbr => [g, b, r],
+            ColorOrder::Brg => [b, r, g],
+            ColorOrder::Bgr => [b, g, r],
+        }
+    }
+}
+
+
+/// One-wire bit timings
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(not(target_os = "espidf"), allow(dead_code))]
+pub struct Timing {
+    pub t0h: Duration,
+    pub t0l: Duration,
+    pub t1h: Duration,
+    pub t1l: Duration,
+    pub reset: Duration,
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Chipset {
+    Ws2811,
+    Ws2812b,
+    Sk6812,
+    Sk6812Rgbw,
+    Ws2815,
+    Tm1814,
+    // Clocked with separate data and clock lines
+    Apa102,
+    Sk9822,
+    Ws2801,
+}
+
+impl Chipset {
+    #[cfg(test)]
+    pub const ALL: [Chipset; 9] = [
+        Chipset::Ws2811, Chipset::Ws2812b, Chipset::Sk6812, Chipset::Sk6812Rgbw, Chipset::Ws2815,
+        Chipset::Tm1814, Chipset::Apa102, Chipset::Sk9822, Chipset::Ws2801,
+    ];
+
+    /// None for clocked chipsets, which follow whatever clock they are given
+    pub fn timing(&self) -> Option<Timing> {
+        let ns = Duration::from_nanos;
+        let us = Duration::from_micros;
+
+        // Resets include some margin over the datasheet minimums
+        match self {
+            // https://cdn-shop.adafruit.com/datasheets/WS2811.pdf
+            Chipset::Ws2811 => Some(Timing { t0h: ns(500), t0l: ns(2000), t1h: ns(1200), t1l: ns(1300), reset: us(60) }),
+            Chipset::Ws2812b => Some(Timing { t0h: ns(400), t0l: ns(850), t1h: ns(800), t1l: ns(450), reset: us(300) }),
+            Chipset::Sk6812 | Chipset::Sk6812Rgbw => Some(Timing { t0h: ns(300), t0l: ns(900), t1h: ns(600), t1l: ns(600), reset: us(90) }),
+            Chipset::Ws2815 => Some(Timing { t0h: ns(300), t0l: ns(1000), t1h: ns(1000), t1l: ns(300), reset: us(300) }),
+            Chipset::Tm1814 => Some(Timing { t0h: ns(360), t0l: ns(890), t1h: ns(720), t1l: ns(530), reset: us(250) }),
+            Chipset::Apa102 | Chipset::Sk9822 | Chipset::Ws2801 => None,
+        }
+    }
+
+    pub fn clocked(&self) -> bool {
+        self.timing().is_none()
+    }
+
+    pub fn default_order(&self) -> ColorOrder {
+        match self {
+            Chipset::Ws2811 | Chipset::Ws2801 | Chipset::Tm1814 => ColorOrder::Rgb,
+            Chipset::Apa102 | Chipset::Sk9822 => ColorOrder::Bgr,
+            _ => ColorOrder::Grb,
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        match self {
+            Chipset::Sk6812Rgbw | Chipset::Tm1814 => 4,
+            _ => 3,
+        }
+    }
+
+    /// TM1814 idles high and sends each bit as low then high
+    #[cfg_attr(not(target_os = "espidf"), allow(dead_code))]
+    pub fn inverted(&self) -> bool {
+        matches!(self, Chipset::Tm1814)
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StripConfig {
+    /// GPIO the data line is connected to. Only read at boot
+    pub pin: u8,
+    pub led_count: usize,
+    pub chipset: Chipset,
+    /// Overrides the chipset's usual order
+    pub color_order: Option<ColorOrder>,
+    /// Only used by chipsets with a white channel
+    pub white_mode: WhiteMode,
+    /// 5-bit brightness (0-31) sent with every pixel on APA102 and SK9822
+    pub global_brightness: u8,
+}
+
+impl StripConfig {
+    /// Every bit is a 4 byte RMT item while the frame is sent
+    pub const MAX_LED_COUNT: usize = 1024;
+    /// Most bytes `encode_frame` gives for a strip of `MAX_LED_COUNT`
+    #[cfg_attr(not(target_os = "espidf"), allow(dead_code))]
+    pub const MAX_FRAME_SIZE: usize = frame_size(Self::MAX_LED_COUNT);
+
+    pub fn validate(&self) -> Result<()> {
+        if !OUTPUT_PINS.contains(&self.pin) {
+            return Err(Error::msg(format!("GPIO{} can't drive a strip, use one of {OUTPUT_PINS:?}", self.pin)))
+        }
+        if self.led_count == 0 || self.led_count > Self::MAX_LED_COUNT {
+            return Err(Error::msg(format!("Strip on pin {} needs between 1 and {} LEDs", self.pin, Self::MAX_LED_COUNT)))
+        }
+        if self.global_brightness > 31 {
+            return Err(Error::msg(format!("Global brightness {} over maximum of 31", self.global_brightness)))
+        }
+        Ok(())
+    }
+
+    pub fn color_order(&self) -> ColorOrder {
+        self.color_order.unwrap_or_else(|| self.chipset.default_order())
+    }
+
+    /// Bytes for a whole frame, including any start and end frames the chip needs
+    #[cfg_attr(not(target_os = "espidf"), allow(dead_code))] // Only sent from the ESP32
+    pub fn encode_frame(&self, frame: &Segment) -> Vec<u8> {
+        let led_count = frame.leds().len();
+        let mut data = Vec::with_capacity(frame_size(led_count));
+
+        match self.chipset {
+            // Constant current setting for each channel (WRGB) at the maximum, followed by its
+            // complement as a checksum
+            Chipset::Tm1814 => {
+                let current = [0x3f; 4];
+                data.extend_from_slice(&current);
+                data.extend(current.iter().map(|c| !c));
+            },
+            Chipset::Apa102 | Chipset::Sk9822 => data.extend_from_slice(&[0; 4]),
+            _ => (),
+        }
+
+        for led in frame.leds() {
+            self.encode(led.color(), &mut data);
+        }
+
+        // https://cpldcpu.wordpress.com/2016/12/13/sk9822-a-clone-of-the-apa102/
+        if self.chipset == Chipset::Sk9822 {
+            // Latches the new colors
+            data.extend_from_slice(&[0; 4]);
+        }
+        if matches!(self.chipset, Chipset::Apa102 | Chipset::Sk9822) {
+            // Each pixel delays the data by half a clock, so push it through to the end of the strip
+            data.resize(data.len() + (led_count + 15) / 16, 0);
+        }
+
+        data
+    }
+
+    /// Appends the bytes for one pixel in the order the chip expects
+    fn encode(&self, color: Color, output: &mut Vec<u8>) {
+        let channels = self.color_order().arrange(color);
+        match self.chipset {
+            Chipset::Apa102 | Chipset::Sk9822 => {
+                output.push(0xe0 | self.global_brightness);
+                output.extend_from_slice(&channels);
+            },
+            Chipset::Tm1814 => {
+                output.push(color.w);
+                output.extend_from_slice(&channels);
+            },
+            Chipset::Sk6812Rgbw => {
+                output.extend_from_slice(&channels);
+                output.push(color.w);
+            },
+            _ => output.extend_from_slice(&channels),
+        }
+    }
+}
+
+impl Default for StripConfig {
+    fn default() -> Self {
+        Self {
+            pin: 15,
+            led_count: DEFAULT_LED_COUNT,
+            chipset: Chipset::Ws2811,
+            color_order: None,
+            white_mode: WhiteMode::default(),
+            global_brightness: 31,
+        }
+    }
+}
+
+
+/// Room for a frame of `led_count` pixels in any chipset: up to 4 bytes a pixel, 8 bytes of
+/// start and end frames and a bit of padding for every other pixel on APA102 and SK9822
+const fn frame_size(led_count: usize) -> usize {
+    8 + led_count * 4 + (led_count + 15) / 16
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_pins_that_cant_drive_a_strip() {
+        for pin in [1, 3, 6, 11, 20, 24, 34, 39, 40, 255] {
+            assert!(StripConfig { pin, ..Default::default() }.validate().is_err(), "GPIO{pin}");
+        }
+        for pin in OUTPUT_PINS {
+            assert!(StripConfig { pin, ..Default::default() }.validate().is_ok(), "GPIO{pin}");
+        }
+    }
+
+    #[test]
+    fn limits_led_count() {
+        assert!(StripConfig { led_count: 0, ..Default::default() }.validate().is_err());
+        assert!(StripConfig { led_count: StripConfig::MAX_LED_COUNT, ..Default::default() }.validate().is_ok());
+        assert!(StripConfig { led_count: StripConfig::MAX_LED_COUNT + 1, ..Default::default() }.validate().is_err());
+    }
+
+    #[test]
+    fn frames_fit_in_max_frame_size() {
+        let frame = Segment::new(StripConfig::MAX_LED_COUNT);
+        for chipset in Chipset::ALL {
+            let strip = StripConfig { chipset, led_count: StripConfig::MAX_LED_COUNT, ..Default::default() };
+            assert!(strip.encode_frame(&frame).len() <= StripConfig::MAX_FRAME_SIZE, "{chipset:?}");
+        }
+    }
+}
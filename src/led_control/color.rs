@@ -1,54 +1,199 @@
+use serde::{Serialize, Deserialize};
+
 
 #[derive(Clone, Copy, Debug)]
 pub struct Color {
     pub r: u8,
     pub g: u8,
     pub b: u8,
+    /// Dedicated white die on RGBW strips. Folded into RGB for strips without one
+    pub w: u8,
+}
+
+/// Hue, saturation and value. Hue covers the full color wheel in 0-255, so 128 is cyan and
+/// it wraps back around to red
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Hsv {
+    pub h: u8,
+    pub s: u8,
+    pub v: u8,
 }
 
+/// Hue, saturation and lightness. Hue is the same as for `Hsv`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Hsl {
+    pub h: u8,
+    pub s: u8,
+    pub l: u8,
+}
+
+// Hue scaled by 6 so each sextant of the color wheel is 256 wide
+const SEXTANT: i32 = 256;
+const HUE_RANGE: i32 = 6 * SEXTANT;
+
 impl Color {
     pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
         Self {
-            r, g, b
+            r, g, b, w: 0
         }
     }
 
-    /*
-    pub fn hsv(h: u8, s: u8, v: u8) -> Self {
-        // https://en.wikipedia.org/wiki/HSL_and_HSV
-        let h_deg = (h as f32) / 255.0 * 360.0;
-        let s_norm = (s as f32) / 255.0;
-        let v_norm = (v as f32) / 255.0;
-
-        let c = v_norm * s_norm;
-        let h_dash = h_deg / 60.0;
-        let x = c * (1.0 - f32::abs(h_dash % 2.0 - 1.0));
-
-        let (r1, g1, b1) =
-            if h_dash < 1.0      { (c, x, 0.0) }
-            else if h_dash < 2.0 { (x, c, 0.0) }
-            else if h_dash < 3.0 { (0.0, c, x) }
-            else if h_dash < 4.0 { (0.0, x, c) }
-            else if h_dash < 5.0 { (x, 0.0, c) }
-            else if h_dash < 6.0 { (c, 0.0, x) }
-            else {
-                //shouldn't be reachable
-                (0.0, 0.0, 0.0)
-            };
-        
-        let m = v_norm - c;
-        
+    pub const fn rgbw(r: u8, g: u8, b: u8, w: u8) -> Self {
         Self {
-            r: ((r1 + m) * 255.0) as u8,
-            g: ((g1 + m) * 255.0) as u8,
-            b: ((b1 + m) * 255.0) as u8,
+            r, g, b, w
         }
     }
-    */
+
+    pub fn hsv(h: u8, s: u8, v: u8) -> Self {
+        // https://en.wikipedia.org/wiki/HSL_and_HSV
+        let chroma = div_round(v as i32 * s as i32, 255);
+        Self::from_hue(h, chroma, v as i32 - chroma)
+    }
+
+    pub fn hsl(h: u8, s: u8, l: u8) -> Self {
+        let l = l as i32;
+        let chroma = div_round((255 - (2 * l - 255).abs()) * s as i32, 255);
+        Self::from_hue(h, chroma, l - div_round(chroma, 2))
+    }
 
     pub fn black() -> Self {
         Self::rgb(0, 0, 0)
     }
+
+    pub fn to_hsv(self) -> Hsv {
+        let (max, min) = self.max_min();
+        let chroma = max - min;
+
+        Hsv {
+            h: self.hue(max, chroma),
+            s: if max == 0 { 0 } else { div_round(chroma * 255, max) as u8 },
+            v: max as u8,
+        }
+    }
+
+    #[allow(dead_code)] // Not every helper for effects is used by the built-in ones
+    pub fn to_hsl(self) -> Hsl {
+        let (max, min) = self.max_min();
+        let chroma = max - min;
+        let sum = max + min;
+
+        Hsl {
+            h: self.hue(max, chroma),
+            s: if chroma == 0 { 0 } else { div_round(chroma * 255, 255 - (sum - 255).abs()) as u8 },
+            l: div_round(sum, 2) as u8,
+        }
+    }
+
+    /// Moves the hue around the color wheel, keeping saturation and value
+    #[allow(dead_code)]
+    pub fn rotate_hue(self, amount: u8) -> Self {
+        let hsv = self.to_hsv();
+        Self::hsv(hsv.h.wrapping_add(amount), hsv.s, hsv.v).with_white(self.w)
+    }
+
+    #[allow(dead_code)]
+    pub fn adjust_saturation(self, amount: i16) -> Self {
+        let hsv = self.to_hsv();
+        Self::hsv(hsv.h, (hsv.s as i16 + amount).clamp(0, 255) as u8, hsv.v).with_white(self.w)
+    }
+
+    #[allow(dead_code)]
+    pub fn adjust_value(self, amount: i16) -> Self {
+        let hsv = self.to_hsv();
+        Self::hsv(hsv.h, hsv.s, (hsv.v as i16 + amount).clamp(0, 255) as u8).with_white(self.w)
+    }
+
+    pub fn with_white(self, w: u8) -> Self {
+        Self { w, ..self }
+    }
+
+    /// Splits the white shared by all three channels out onto the white channel
+    pub fn extract_white(self, mode: WhiteMode) -> Self {
+        let white = self.r.min(self.g).min(self.b);
+        match mode {
+            WhiteMode::None => self,
+            WhiteMode::Accurate => Self::rgbw(self.r - white, self.g - white, self.b - white, self.w.max(white)),
+            WhiteMode::Brighter => self.with_white(self.w.max(white)),
+        }
+    }
+
+    /// Adds the white channel onto RGB, for strips without a white die
+    pub fn fold_white(self) -> Self {
+        Self::rgb(self.r.saturating_add(self.w), self.g.saturating_add(self.w), self.b.saturating_add(self.w))
+    }
+
+    /// Scales each of R, G, B and W by its own factor, e.g. to tint a color
+    pub fn scale_channels(self, [r, g, b, w]: [f32; 4]) -> Self {
+        Self::rgbw(
+            (self.r as f32 * r).round() as u8,
+            (self.g as f32 * g).round() as u8,
+            (self.b as f32 * b).round() as u8,
+            (self.w as f32 * w).round() as u8,
+        )
+    }
+
+    /// Linearly interpolates towards `other`, where `t` of 0.0 is `self` and 1.0 is `other`
+    pub fn blend(self, other: Color, t: f32) -> Self {
+        let t = t.clamp(0.0, 1.0);
+        self * (1.0 - t) + other * t
+    }
+
+    /// Interpolates in HSV space, taking the shorter way around the color wheel. Unlike
+    /// `blend` this keeps colors saturated, e.g. red to green passes through yellow
+    #[allow(dead_code)]
+    pub fn blend_hsv(self, other: Color, t: f32) -> Self {
+        let t = t.clamp(0.0, 1.0);
+        let (from, to) = (self.to_hsv(), other.to_hsv());
+
+        let hue_diff = to.h.wrapping_sub(from.h) as i8;
+        let lerp = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+
+        Self::hsv(
+            from.h.wrapping_add((hue_diff as f32 * t).round() as i8 as u8),
+            lerp(from.s, to.s),
+            lerp(from.v, to.v),
+        ).with_white(lerp(self.w, other.w))
+    }
+
+    fn from_hue(h: u8, chroma: i32, offset: i32) -> Self {
+        let hue = h as i32 * HUE_RANGE / 256;
+        let sextant = hue / SEXTANT;
+        // Rises then falls across each pair of sextants
+        let x = div_round(chroma * (SEXTANT - (hue % (2 * SEXTANT) - SEXTANT).abs()), SEXTANT);
+
+        let (r, g, b) = match sextant {
+            0 => (chroma, x, 0),
+            1 => (x, chroma, 0),
+            2 => (0, chroma, x),
+            3 => (0, x, chroma),
+            4 => (x, 0, chroma),
+            _ => (chroma, 0, x),
+        };
+
+        Self::rgb((r + offset) as u8, (g + offset) as u8, (b + offset) as u8)
+    }
+
+    fn max_min(self) -> (i32, i32) {
+        let (r, g, b) = (self.r as i32, self.g as i32, self.b as i32);
+        (r.max(g).max(b), r.min(g).min(b))
+    }
+
+    fn hue(self, max: i32, chroma: i32) -> u8 {
+        if chroma == 0 {
+            return 0
+        }
+
+        let (r, g, b) = (self.r as i32, self.g as i32, self.b as i32);
+        let hue = if max == r {
+            div_round((g - b) * SEXTANT, chroma)
+        } else if max == g {
+            2 * SEXTANT + div_round((b - r) * SEXTANT, chroma)
+        } else {
+            4 * SEXTANT + div_round((r - g) * SEXTANT, chroma)
+        };
+
+        (div_round(hue.rem_euclid(HUE_RANGE) * 256, HUE_RANGE) % 256) as u8
+    }
 }
 
 impl Default for Color {
@@ -57,13 +202,119 @@ impl Default for Color {
     }
 }
 
+impl From<Hsv> for Color {
+    fn from(hsv: Hsv) -> Self {
+        Self::hsv(hsv.h, hsv.s, hsv.v)
+    }
+}
+
+impl From<Hsl> for Color {
+    fn from(hsl: Hsl) -> Self {
+        Self::hsl(hsl.h, hsl.s, hsl.l)
+    }
+}
+
 impl std::ops::Mul<f32> for Color {
     type Output = Color;
     fn mul(self, rhs: f32) -> Color {
         Color {
             r: (self.r as f32 * rhs).round() as u8,
             g: (self.g as f32 * rhs).round() as u8,
-            b: (self.b as f32 * rhs).round() as u8
+            b: (self.b as f32 * rhs).round() as u8,
+            w: (self.w as f32 * rhs).round() as u8,
         }
     }
-}
\ No newline at end of file
+}
+
+impl std::ops::Add for Color {
+    type Output = Color;
+    fn add(self, rhs: Color) -> Color {
+        Color {
+            r: self.r.saturating_add(rhs.r),
+            g: self.g.saturating_add(rhs.g),
+            b: self.b.saturating_add(rhs.b),
+            w: self.w.saturating_add(rhs.w),
+        }
+    }
+}
+
+
+/// How white is pulled out of RGB for strips with a white channel
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WhiteMode {
+    /// Only white set explicitly by effects is used
+    None,
+    /// Moves the common part of RGB onto white, so colors look the same but whites are cleaner
+    #[default]
+    Accurate,
+    /// Adds the common part of RGB onto white while keeping RGB, for more light output
+    Brighter,
+}
+
+
+/// Integer division rounding to nearest for non-negative divisors
+fn div_round(num: i32, div: i32) -> i32 {
+    if num >= 0 {
+        (num + div / 2) / div
+    } else {
+        (num - div / 2) / div
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Largest difference between any channel of `a` and `b`
+    fn distance(a: Color, b: Color) -> u8 {
+        [a.r.abs_diff(b.r), a.g.abs_diff(b.g), a.b.abs_diff(b.b), a.w.abs_diff(b.w)].into_iter().max().unwrap_or_default()
+    }
+
+    fn every_rgb() -> impl Iterator<Item = Color> {
+        (0..=255).flat_map(|r| (0..=255).flat_map(move |g| (0..=255).map(move |b| Color::rgb(r, g, b))))
+    }
+
+    #[test]
+    fn rgb_round_trips_through_hsv() {
+        let worst = every_rgb().max_by_key(|&color| distance(color, color.to_hsv().into())).unwrap();
+        assert!(distance(worst, worst.to_hsv().into()) <= 4, "{worst:?} came back as {:?}", Color::from(worst.to_hsv()));
+    }
+
+    #[test]
+    fn rgb_round_trips_through_hsl() {
+        let worst = every_rgb().max_by_key(|&color| distance(color, color.to_hsl().into())).unwrap();
+        assert!(distance(worst, worst.to_hsl().into()) <= 4, "{worst:?} came back as {:?}", Color::from(worst.to_hsl()));
+    }
+
+    #[test]
+    fn primaries_land_on_the_wheel() {
+        assert_eq!(Color::rgb(255, 0, 0).to_hsv(), Hsv { h: 0, s: 255, v: 255 });
+        assert_eq!(Color::rgb(0, 255, 255).to_hsv(), Hsv { h: 128, s: 255, v: 255 });
+        assert_eq!(Color::rgb(128, 128, 128).to_hsl(), Hsl { h: 0, s: 0, l: 128 });
+        assert_eq!(distance(Color::hsv(0, 255, 255), Color::rgb(255, 0, 0)), 0);
+        assert_eq!(distance(Color::hsl(0, 255, 128), Color::rgb(255, 0, 0)), 1);
+    }
+
+    #[test]
+    fn hue_operations_keep_white() {
+        let color = Color::rgbw(255, 0, 0, 40);
+        assert_eq!(color.rotate_hue(0).to_hsv(), color.to_hsv());
+        assert_eq!(color.rotate_hue(128).to_hsv().h, 128);
+        assert_eq!(color.rotate_hue(128).w, 40);
+        assert_eq!(color.adjust_saturation(-255).to_hsv().s, 0);
+        assert_eq!(color.adjust_value(100).to_hsv().v, 255);
+    }
+
+    #[test]
+    fn blend_hsv_takes_the_short_way_round() {
+        let (red, green) = (Color::rgb(255, 0, 0), Color::rgb(0, 255, 0));
+        let middle = red.blend_hsv(green, 0.5);
+        assert!(middle.r > 200 && middle.g > 200 && middle.b < 10, "{middle:?} isn't yellow");
+
+        // Magenta to red crosses 0 rather than going back through green
+        let magenta = Color::rgb(255, 0, 255);
+        assert!(magenta.blend_hsv(red, 0.5).g < 10);
+    }
+}
@@ -1,59 +1,131 @@
 use crate::effects::*;
 use super::segment::Segment;
-use super::color::Color;
-use super::LED_COUNT;
+use super::output::Output;
+use super::group::OutputGroup;
+use super::logical::{LogicalSegment, SegmentConfig};
+use super::pipeline::{OutputPipeline, OutputConfig};
+use super::pixel_map::PixelMap;
+use super::preset::{Playlist, PlaylistPlayer, PlaylistStatus, Preset, PresetLibrary};
+use super::power::PowerStatus;
+use super::realtime::{RealtimeFrame, RealtimeSource, RealtimeUpdate};
+use super::transition::TransitionConfig;
 
 use anyhow::{Result, Error};
+use rand::SeedableRng;
+#[cfg(target_os = "espidf")]
 use esp_idf_hal::{
-    rmt::{
-        Pulse,
-        PinState,
-        VariableLengthSignal,
-        TxRmtDriver,
-        config::{Loop, TransmitConfig},
-        RmtChannel
-    },
-    peripheral,
-    gpio::OutputPin,
     task::thread::ThreadSpawnConfiguration,
     cpu::Core,
 };
 
-use std::time::Duration;
-use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime};
+use std::sync::{Arc, Mutex, mpsc};
 use std::thread;
 
 
-const CLOCK_DIV: u8 = 8; // 10MHz with an 80 MHz clock on the ESP32
-const REFRESH_RATE: Duration = Duration::from_millis(20);
+pub const REFRESH_RATE: Duration = Duration::from_millis(20);
+
+const SEGMENTS_MUTEX_ERR: &str = "Failed to unlock current segments mutex";
+const OUTPUT_MUTEX_ERR: &str = "Failed to unlock current output config mutex";
+const POWER_MUTEX_ERR: &str = "Failed to unlock power status mutex";
+const MAP_MUTEX_ERR: &str = "Failed to unlock pixel map mutex";
+const LIBRARY_MUTEX_ERR: &str = "Failed to unlock preset library mutex";
+const PLAYLIST_MUTEX_ERR: &str = "Failed to unlock playlist status mutex";
+const PRESET_MUTEX_ERR: &str = "Failed to unlock applied preset mutex";
+const REALTIME_MUTEX_ERR: &str = "Failed to unlock realtime status mutex";
+
+
+#[cfg_attr(not(target_os = "espidf"), allow(dead_code))] // Mostly sent by the server
+pub enum ControllerCommand {
+    /// Switches the effect of the segment at the given index
+    SetEffect(usize, EffectConfig, TransitionConfig),
+    SetSegments(Vec<SegmentConfig>),
+    /// Reseeds the effect rng and restarts the current effects, so devices given the same
+    /// seed show the same thing
+    Reseed(u64),
+    /// The result is sent back once it has been applied, if anyone is waiting for it
+    SetOutput(OutputConfig, Option<mpsc::Sender<Result<()>>>),
+    /// Positions of every LED for spatial effects, or None to go back to laying them out
+    /// along the strip
+    SetMap(Option<PixelMap>),
+    /// Adds the preset to the library, or replaces the one with the same name. The result is
+    /// sent back once it has been saved
+    SavePreset(Preset, mpsc::Sender<Result<()>>),
+    DeletePreset(String),
+    /// Switches to the named preset, stopping any playlist
+    ApplyPreset(String, TransitionConfig),
+    SavePlaylist(Playlist),
+    DeletePlaylist(String),
+    PlayPlaylist(String),
+    StopPlaylist,
+    /// Replaces the output config, segments, pixel map and preset library all at once, or
+    /// leaves everything as it was if any of it can't be applied. The result is sent back
+    /// once it has been applied
+    SetConfig(Box<DeviceState>, mpsc::Sender<Result<()>>),
+    /// Pixels from a realtime source, shown instead of the effects until the stream times out
+    Realtime(RealtimeUpdate),
+    /// Hands back to the effects straight away if the given source is the one being shown
+    EndRealtime(RealtimeSource),
+}
 
-// From WS2811 datasheet https://cdn-shop.adafruit.com/datasheets/WS2811.pdf
-const T0H: Duration = Duration::from_nanos(500);
-const T1H: Duration = Duration::from_nanos(1200);
-const T0L: Duration = Duration::from_nanos(2000);
-const T1L: Duration = Duration::from_nanos(1300);
-const RES: Duration = Duration::from_micros(60); // Add 10 us margin
 
+/// Everything `ControllerCommand::SetConfig` replaces
+pub struct DeviceState {
+    pub output: OutputConfig,
+    pub segments: Vec<SegmentConfig>,
+    pub map: Option<PixelMap>,
+    pub library: PresetLibrary,
+}
 
-// Initialized on first instance of LEDController being created
-static PULSES_HIGH: OnceLock<[Pulse; 2]> = OnceLock::new();
-static PULSES_LOW: OnceLock<[Pulse; 2]> = OnceLock::new();
-static PULSE_RESET: OnceLock<[Pulse; 2]> = OnceLock::new();
+
+/// State the controller publishes for other services to read
+#[derive(Clone)]
+struct SharedState {
+    segments: Arc<Mutex<Vec<SegmentConfig>>>,
+    output: Arc<Mutex<OutputConfig>>,
+    power: Arc<Mutex<PowerStatus>>,
+    map: Arc<Mutex<Option<PixelMap>>>,
+    library: Arc<Mutex<PresetLibrary>>,
+    playlist: Arc<Mutex<Option<PlaylistStatus>>>,
+    /// Last preset applied, until the segments are changed some other way
+    preset: Arc<Mutex<Option<String>>>,
+    realtime: Arc<Mutex<Option<RealtimeSource>>>,
+}
 
 
 pub struct LEDControllerService {
     _handle: thread::JoinHandle<()>,
+    pub controller_tx: mpsc::Sender<ControllerCommand>,
+    shared: SharedState,
 }
 
 
 impl LEDControllerService {
-    pub fn init<C: RmtChannel>(
-        channel: impl peripheral::Peripheral<P = C> + 'static,
-        pin: impl peripheral::Peripheral<P = impl OutputPin> + 'static
+    /// `outputs` are in the same order as the strips in `output_config`
+    pub fn init(
+        outputs: Vec<Box<dyn Output + Send>>,
+        segments: Vec<SegmentConfig>,
+        output_config: OutputConfig,
+        pixel_map: Option<PixelMap>,
+        library: PresetLibrary,
     ) -> Result<Self> {
+        let outputs = OutputGroup::new(outputs, &output_config.strips)?;
+
+        let shared = SharedState {
+            segments: Arc::new(Mutex::new(segments)),
+            output: Arc::new(Mutex::new(output_config)),
+            power: Arc::new(Mutex::new(PowerStatus::default())),
+            map: Arc::new(Mutex::new(pixel_map)),
+            library: Arc::new(Mutex::new(library)),
+            playlist: Arc::new(Mutex::new(None)),
+            preset: Arc::new(Mutex::new(None)),
+            realtime: Arc::new(Mutex::new(None)),
+        };
+        let (controller_tx, controller_rx) = mpsc::channel::<ControllerCommand>();
 
-        let mut led_controller = LEDController::new(channel, pin)?;
+        let mut led_controller = LEDController::new(outputs, controller_rx, shared.clone())?;
 
+        #[cfg(target_os = "espidf")]
         ThreadSpawnConfiguration {
             name: Some(b"Led_Controller\0"),
             priority: 10,
@@ -68,119 +140,443 @@ impl LEDControllerService {
         });
 
         // Set back to defaults.
+        #[cfg(target_os = "espidf")]
         ThreadSpawnConfiguration::default().set().unwrap();
 
         Ok(Self {
             _handle: join_handle,
+            controller_tx,
+            shared,
         })
     }
-}
 
+    #[cfg_attr(not(target_os = "espidf"), allow(dead_code))]
+    pub fn current_segments(&self) -> &Arc<Mutex<Vec<SegmentConfig>>> {
+        &self.shared.segments
+    }
 
-struct LEDController<'a> {
-    segment: Segment,
-    rmt_tx: TxRmtDriver<'a>,
-    effect: Box<dyn Effect + Send>,
-}
+    pub fn current_output(&self) -> &Arc<Mutex<OutputConfig>> {
+        &self.shared.output
+    }
 
+    #[cfg_attr(not(target_os = "espidf"), allow(dead_code))]
+    pub fn power_status(&self) -> &Arc<Mutex<PowerStatus>> {
+        &self.shared.power
+    }
 
-impl<'a> LEDController<'a> {
-    fn new<C: RmtChannel>(
-        channel: impl peripheral::Peripheral<P = C> + 'a,
-        pin: impl peripheral::Peripheral<P = impl OutputPin> + 'a
-    ) -> Result<Self> {
-        let config = TransmitConfig {
-            clock_divider: CLOCK_DIV,
-            mem_block_num: 8,
-            carrier: None,
-            looping: Loop::None,
-            idle: Some(PinState::Low),
-            ..Default::default()
-        };
+    #[cfg_attr(not(target_os = "espidf"), allow(dead_code))]
+    pub fn current_map(&self) -> &Arc<Mutex<Option<PixelMap>>> {
+        &self.shared.map
+    }
 
-        let rmt_tx = TxRmtDriver::new(channel, pin, &config)?;
+    #[cfg_attr(not(target_os = "espidf"), allow(dead_code))]
+    pub fn current_library(&self) -> &Arc<Mutex<PresetLibrary>> {
+        &self.shared.library
+    }
 
+    #[cfg_attr(not(target_os = "espidf"), allow(dead_code))]
+    pub fn playlist_status(&self) -> &Arc<Mutex<Option<PlaylistStatus>>> {
+        &self.shared.playlist
+    }
 
-        let hertz = rmt_tx.counter_clock()?;
+    /// Name of the preset the segments were last set from, if they haven't changed since
+    #[cfg_attr(not(target_os = "espidf"), allow(dead_code))]
+    pub fn applied_preset(&self) -> &Arc<Mutex<Option<String>>> {
+        &self.shared.preset
+    }
 
-        if PULSES_HIGH.set([
-            Pulse::new_with_duration(hertz, PinState::High, &T1H)?,
-            Pulse::new_with_duration(hertz, PinState::Low, &T1L)?,
-        ]).is_err() {
-            log::warn!("PULSES_HIGH already set")
-        }
+    /// The source being shown instead of the effects, if any
+    #[cfg_attr(not(target_os = "espidf"), allow(dead_code))]
+    pub fn realtime_status(&self) -> &Arc<Mutex<Option<RealtimeSource>>> {
+        &self.shared.realtime
+    }
+}
 
-        if PULSES_LOW.set([
-            Pulse::new_with_duration(hertz, PinState::High, &T0H)?,
-            Pulse::new_with_duration(hertz, PinState::Low, &T0L)?,
-        ]).is_err() {
-            log::warn!("PULSES_LOW already set")
-        }
 
-        if PULSE_RESET.set([
-            Pulse::new_with_duration(hertz, PinState::Low, &RES)?,
-            Pulse::new_with_duration(hertz, PinState::Low, &Duration::from_micros(1))?,
-        ]).is_err() {
-            log::warn!("PULSE_RESET already set")
-        }
+struct LEDController {
+    segments: Vec<LogicalSegment>,
+    frame: Segment,
+    led_count: usize,
+    outputs: OutputGroup,
+    pipeline: OutputPipeline,
+    rng: EffectRng,
+    frame_count: u64,
+    start: Instant,
+    last_tick: Instant,
+    playlist: Option<PlaylistPlayer>,
+    realtime: Option<RealtimeFrame>,
+    controller_rx: mpsc::Receiver<ControllerCommand>,
+    shared: SharedState,
+}
 
-        //let effect = Box::new(Rainbow::init(0, 10));
-        let effect = Box::new(crate::effects::SpookyEyes::init(LED_COUNT));
+
+impl LEDController {
+    fn new(
+        outputs: OutputGroup,
+        controller_rx: mpsc::Receiver<ControllerCommand>,
+        shared: SharedState,
+    ) -> Result<Self> {
+        let pipeline = OutputPipeline::new(shared.output.lock().expect(OUTPUT_MUTEX_ERR).clone());
+        let led_count = pipeline.config().led_count();
+        let segments = build_segments(
+            &shared.segments.lock().expect(SEGMENTS_MUTEX_ERR),
+            led_count,
+            shared.map.lock().expect(MAP_MUTEX_ERR).as_ref(),
+        )?;
 
         Ok(Self {
-            segment: Segment::new(LED_COUNT),
-            rmt_tx,
-            effect,
+            segments,
+            frame: Segment::new(led_count),
+            led_count,
+            outputs,
+            pipeline,
+            rng: EffectRng::from_entropy(),
+            frame_count: 0,
+            start: Instant::now(),
+            last_tick: Instant::now(),
+            playlist: None,
+            realtime: None,
+            controller_rx,
+            shared,
         })
     }
 
     fn run(&mut self) -> Result<()> {
         loop {
             thread::sleep(REFRESH_RATE);
+
+            while let Ok(command) = self.controller_rx.try_recv() {
+                self.handle_command(command);
+            }
+
             self.tick()?;
         }
     }
 
-    pub fn tick(&mut self) -> Result<()> {
-
-        self.effect.tick(&mut self.segment)?;
+    fn handle_command(&mut self, command: ControllerCommand) {
+        match command {
+            ControllerCommand::SetEffect(index, config, transition) => {
+                log::info!("Switching effect of segment {index}: {config:?} with {transition:?}");
+                self.stop_playlist();
+                self.forget_preset();
+                let Some(segment) = self.segments.get_mut(index) else {
+                    log::warn!("No segment {index}");
+                    return
+                };
+                match segment.set_effect(config, transition) {
+                    Ok(_) => self.publish_segments(),
+                    Err(e) => log::warn!("Unable to build effect: {e:?}"),
+                }
+            },
+            ControllerCommand::SetSegments(configs) => {
+                log::info!("Switching segments: {configs:?}");
+                self.stop_playlist();
+                self.forget_preset();
+                match build_segments(&configs, self.led_count, self.shared.map.lock().expect(MAP_MUTEX_ERR).as_ref()) {
+                    Ok(segments) => {
+                        self.segments = segments;
+                        self.publish_segments();
+                    },
+                    Err(e) => log::warn!("Unable to build segments: {e:?}"),
+                }
+            },
+            ControllerCommand::Reseed(seed) => {
+                log::info!("Reseeding effects with {seed}");
+                if let Err(e) = self.segments.iter_mut().try_for_each(LogicalSegment::restart) {
+                    log::warn!("Unable to restart effects: {e:?}");
+                    return
+                }
+                self.rng = EffectRng::seed_from_u64(seed);
+                self.frame_count = 0;
+                self.start = Instant::now();
+            },
+            ControllerCommand::SetOutput(config, reply) => {
+                log::info!("Updating output: {config:?}");
+                let result = self.set_output(config);
+                if let Err(e) = &result {
+                    log::warn!("Unable to update output: {e:?}");
+                }
+                if let Some(reply) = reply {
+                    // Nobody is waiting if the request has already timed out
+                    let _ = reply.send(result);
+                }
+            },
+            ControllerCommand::SetMap(map) => {
+                log::info!("Switching pixel map to {} points", map.as_ref().map_or(0, PixelMap::len));
+                let configs = self.shared.segments.lock().expect(SEGMENTS_MUTEX_ERR).clone();
+                match build_segments(&configs, self.led_count, map.as_ref()) {
+                    Ok(segments) => {
+                        self.segments = segments;
+                        *self.shared.map.lock().expect(MAP_MUTEX_ERR) = map;
+                    },
+                    Err(e) => log::warn!("Unable to rebuild effects for pixel map: {e:?}"),
+                }
+            },
+            ControllerCommand::SavePreset(preset, reply) => {
+                log::info!("Saving preset {}", preset.name);
+                let result = self.shared.library.lock().expect(LIBRARY_MUTEX_ERR).save_preset(preset);
+                if let Err(e) = &result {
+                    log::warn!("Unable to save preset: {e:?}");
+                }
+                // Nobody is waiting if the request has already timed out
+                let _ = reply.send(result);
+            },
+            ControllerCommand::DeletePreset(name) => {
+                log::info!("Deleting preset {name}");
+                if let Err(e) = self.shared.library.lock().expect(LIBRARY_MUTEX_ERR).delete_preset(&name) {
+                    log::warn!("Unable to delete preset: {e:?}");
+                }
+            },
+            ControllerCommand::ApplyPreset(name, transition) => {
+                log::info!("Applying preset {name} with {transition:?}");
+                self.stop_playlist();
+                if let Err(e) = self.apply_preset(&name, transition) {
+                    log::warn!("Unable to apply preset {name}: {e:?}");
+                }
+            },
+            ControllerCommand::SavePlaylist(playlist) => {
+                log::info!("Saving playlist {}", playlist.name);
+                if let Err(e) = self.shared.library.lock().expect(LIBRARY_MUTEX_ERR).save_playlist(playlist) {
+                    log::warn!("Unable to save playlist: {e:?}");
+                }
+            },
+            ControllerCommand::DeletePlaylist(name) => {
+                log::info!("Deleting playlist {name}");
+                if self.playlist.as_ref().map_or(false, |player| player.status().playlist == name) {
+                    self.stop_playlist();
+                }
+                if let Err(e) = self.shared.library.lock().expect(LIBRARY_MUTEX_ERR).delete_playlist(&name) {
+                    log::warn!("Unable to delete playlist: {e:?}");
+                }
+            },
+            ControllerCommand::PlayPlaylist(name) => {
+                log::info!("Playing playlist {name}");
+                let Some(playlist) = self.shared.library.lock().expect(LIBRARY_MUTEX_ERR).playlist(&name).cloned() else {
+                    log::warn!("No playlist {name}");
+                    return
+                };
+                let player = PlaylistPlayer::new(playlist);
+                let status = player.status();
+                if let Err(e) = self.apply_preset(&status.preset, TransitionConfig::default()) {
+                    log::warn!("Unable to apply preset {}: {e:?}", status.preset);
+                    return
+                }
+                self.playlist = Some(player);
+                *self.shared.playlist.lock().expect(PLAYLIST_MUTEX_ERR) = Some(status);
+            },
+            ControllerCommand::StopPlaylist => {
+                log::info!("Stopping playlist");
+                self.stop_playlist();
+            },
+            ControllerCommand::SetConfig(state, reply) => {
+                log::info!("Replacing config");
+                let result = self.set_config(*state);
+                if let Err(e) = &result {
+                    log::warn!("Unable to apply config: {e:?}");
+                }
+                // Nobody is waiting if the request has already timed out
+                let _ = reply.send(result);
+            },
+            ControllerCommand::Realtime(update) => {
+                // The latest source to send anything takes over from whichever was shown before
+                let realtime = match &mut self.realtime {
+                    Some(realtime) if realtime.source() == update.source => realtime,
+                    _ => {
+                        log::info!("Showing realtime data from {:?}", update.source);
+                        *self.shared.realtime.lock().expect(REALTIME_MUTEX_ERR) = Some(update.source);
+                        self.realtime.insert(RealtimeFrame::new(update.source, self.led_count))
+                    },
+                };
+                realtime.apply(update);
+            },
+            ControllerCommand::EndRealtime(source) => {
+                if self.realtime.as_ref().map_or(false, |realtime| realtime.source() == source) {
+                    self.end_realtime();
+                }
+            },
+        }
+    }
 
-        for led in self.segment.leds_mut() {
-            led.set(Color::rgb(led.color().r / 2, led.color().g / 2, led.color().b / 2));
+    /// Effects are laid out for a fixed length, so resized ones are built before anything is
+    /// switched over
+    fn set_output(&mut self, config: OutputConfig) -> Result<()> {
+        if config.needs_restart(self.pipeline.config()) {
+            return Err(Error::msg("Changing strip pins or between one-wire and clocked chipsets needs a restart"))
         }
 
-        self.send_signal()?;
+        let led_count = config.led_count();
+        let resized_segments = if led_count != self.led_count {
+            let configs = self.shared.segments.lock().expect(SEGMENTS_MUTEX_ERR).clone();
+            SegmentConfig::validate_all(&configs, led_count)?;
+            let segments = build_segments(&configs, led_count, self.shared.map.lock().expect(MAP_MUTEX_ERR).as_ref())
+                .map_err(|e| e.context(format!("Unable to rebuild effects for {led_count} LEDs")))?;
+            Some(segments)
+        } else {
+            None
+        };
 
+        self.outputs.set_strips(&config.strips)?;
+
+        if let Some(segments) = resized_segments {
+            log::info!("Resizing from {} to {led_count} LEDs", self.led_count);
+            self.segments = segments;
+            self.frame = Segment::new(led_count);
+            self.led_count = led_count;
+        }
+        self.pipeline.set_config(config.clone());
+        *self.shared.output.lock().expect(OUTPUT_MUTEX_ERR) = config;
         Ok(())
     }
 
-    fn send_signal(&mut self) -> Result<()> {
-        let mut signal = VariableLengthSignal::new();
-        
-        for led in self.segment.leds() {
-            write_color(&mut signal, led.color())?;
+    /// Builds everything for the new config before touching what is running
+    fn set_config(&mut self, state: DeviceState) -> Result<()> {
+        if state.output.needs_restart(self.pipeline.config()) {
+            return Err(Error::msg("Changing strip pins or between one-wire and clocked chipsets needs a restart"))
         }
 
-        signal.push(PULSE_RESET.get().unwrap())?;
+        let led_count = state.output.led_count();
+        let segments = build_segments(&state.segments, led_count, state.map.as_ref())?;
+        self.outputs.set_strips(&state.output.strips)?;
 
-        self.rmt_tx.start_blocking(&signal)?;
+        self.stop_playlist();
+        self.forget_preset();
+        self.segments = segments;
+        self.frame = Segment::new(led_count);
+        self.led_count = led_count;
+        self.pipeline.set_config(state.output.clone());
+
+        *self.shared.output.lock().expect(OUTPUT_MUTEX_ERR) = state.output;
+        *self.shared.map.lock().expect(MAP_MUTEX_ERR) = state.map;
+        *self.shared.library.lock().expect(LIBRARY_MUTEX_ERR) = state.library;
+        self.publish_segments();
 
         Ok(())
     }
-}
 
+    /// Switches to the named preset. Segments that only differ in their effect crossfade,
+    /// anything else is rebuilt
+    fn apply_preset(&mut self, name: &str, transition: TransitionConfig) -> Result<()> {
+        let preset = self.shared.library.lock().expect(LIBRARY_MUTEX_ERR).preset(name).cloned()
+            .ok_or_else(|| Error::msg(format!("No preset {name}")))?;
+
+        let same_layout = preset.segments.len() == self.segments.len()
+            && preset.segments.iter().zip(&self.segments).all(|(config, segment)| {
+                SegmentConfig { effect: config.effect.clone(), ..segment.config().clone() } == *config
+            });
+        if same_layout {
+            for (segment, config) in self.segments.iter_mut().zip(preset.segments) {
+                if segment.config().effect != config.effect {
+                    segment.set_effect(config.effect, transition)?;
+                }
+            }
+        } else {
+            self.segments = build_segments(&preset.segments, self.led_count, self.shared.map.lock().expect(MAP_MUTEX_ERR).as_ref())?;
+        }
+        self.publish_segments();
+
+        if preset.brightness != self.pipeline.config().brightness {
+            let config = OutputConfig { brightness: preset.brightness, ..self.pipeline.config().clone() };
+            self.pipeline.set_config(config.clone());
+            *self.shared.output.lock().expect(OUTPUT_MUTEX_ERR) = config;
+        }
+
+        *self.shared.preset.lock().expect(PRESET_MUTEX_ERR) = Some(preset.name);
+        Ok(())
+    }
 
-fn write_color(signal: &mut VariableLengthSignal, color: Color) -> Result<()> {
-    // For WS2811 sent as RGB with the MSB first
-    for val in [color.r, color.g, color.b] {
-        for shift in (0..8).rev() {
-            if val & (1 << shift) == 0 {
-                signal.push(PULSES_LOW.get().ok_or(Error::msg("PULSES_LOW not set"))?)?;
-            } else {
-                signal.push(PULSES_HIGH.get().ok_or(Error::msg("PULSES_HIGH not set"))?)?;
+    fn advance_playlist(&mut self, delta: Duration) {
+        let Some(player) = &mut self.playlist else { return };
+        let Some(preset) = player.tick(delta).map(str::to_string) else {
+            if player.finished() {
+                log::info!("Playlist finished");
+                self.stop_playlist();
             }
+            return
+        };
+        let status = player.status();
+
+        if let Err(e) = self.apply_preset(&preset, TransitionConfig::default()) {
+            log::warn!("Unable to apply preset {preset}: {e:?}");
         }
+        *self.shared.playlist.lock().expect(PLAYLIST_MUTEX_ERR) = Some(status);
     }
 
-    Ok(())
-}
\ No newline at end of file
+    fn stop_playlist(&mut self) {
+        self.playlist = None;
+        *self.shared.playlist.lock().expect(PLAYLIST_MUTEX_ERR) = None;
+    }
+
+    fn forget_preset(&mut self) {
+        *self.shared.preset.lock().expect(PRESET_MUTEX_ERR) = None;
+    }
+
+    fn end_realtime(&mut self) {
+        if let Some(realtime) = self.realtime.take() {
+            log::info!("Realtime data from {:?} stopped, back to effects", realtime.source());
+        }
+        *self.shared.realtime.lock().expect(REALTIME_MUTEX_ERR) = None;
+    }
+
+    fn publish_segments(&self) {
+        *self.shared.segments.lock().expect(SEGMENTS_MUTEX_ERR) = self.segments.iter()
+            .map(|segment| segment.config().clone())
+            .collect();
+    }
+
+    pub fn tick(&mut self) -> Result<()> {
+        let now = Instant::now();
+        let delta = now - self.last_tick;
+        self.advance_playlist(delta);
+        if self.realtime.as_ref().map_or(false, RealtimeFrame::expired) {
+            self.end_realtime();
+        }
+
+        let mut ctx = FrameContext {
+            frame: self.frame_count,
+            delta,
+            elapsed: now - self.start,
+            time: SystemTime::now(),
+            rng: &mut self.rng,
+        };
+        self.last_tick = now;
+        self.frame_count += 1;
+
+        // Segments draw over each other in order, anything not covered stays off. Effects are
+        // paused while a realtime source is being shown
+        self.frame.turn_off();
+        if !self.realtime.as_ref().map_or(false, |realtime| realtime.draw(&mut self.frame)) {
+            for segment in &mut self.segments {
+                segment.tick(&mut ctx, &mut self.frame)?;
+            }
+        }
+
+        // Applied to the composited frame only, so effect buffers keep their own colors
+        let power = self.pipeline.apply_to(&mut self.frame);
+        *self.shared.power.lock().expect(POWER_MUTEX_ERR) = power;
+
+        self.outputs.write(&self.frame)?;
+
+        Ok(())
+    }
+}
+
+
+fn build_segments(configs: &[SegmentConfig], led_count: usize, map: Option<&PixelMap>) -> Result<Vec<LogicalSegment>> {
+    if configs.is_empty() {
+        return Err(Error::msg("At least one segment is needed"))
+    }
+
+    // A map for a different number of LEDs is kept until one that fits is uploaded, or the
+    // strips are changed back to match it
+    let map = map.filter(|map| {
+        let fits = map.len() == led_count;
+        if !fits {
+            log::warn!("Ignoring pixel map of {} points for {led_count} LEDs", map.len());
+        }
+        fits
+    });
+
+    configs.iter()
+        .map(|config| LogicalSegment::new(config.clone(), led_count, map))
+        .collect()
+}
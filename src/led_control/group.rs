@@ -0,0 +1,66 @@
+use super::output::Output;
+use super::segment::Segment;
+use super::chipset::StripConfig;
+
+use anyhow::{Result, Error};
+
+
+/// Splits the logical pixel space across the physical outputs in order, so the first strip
+/// shows the first `led_count` pixels, the next strip the ones after and so on
+pub struct OutputGroup {
+    outputs: Vec<Box<dyn Output + Send>>,
+    strips: Vec<StripConfig>,
+    buffers: Vec<Segment>,
+}
+
+
+impl OutputGroup {
+    pub fn new(outputs: Vec<Box<dyn Output + Send>>, strips: &[StripConfig]) -> Result<Self> {
+        if outputs.len() != strips.len() {
+            return Err(Error::msg(format!("{} outputs for {} strips", outputs.len(), strips.len())))
+        }
+
+        Ok(Self {
+            outputs,
+            strips: strips.to_vec(),
+            buffers: strips.iter().map(|strip| Segment::new(strip.led_count)).collect(),
+        })
+    }
+
+    /// Switches chipsets and lengths. The pins have to stay the same
+    pub fn set_strips(&mut self, strips: &[StripConfig]) -> Result<()> {
+        let outputs = self.outputs.iter_mut().zip(self.strips.iter_mut()).zip(self.buffers.iter_mut());
+        for (((output, current), buffer), strip) in outputs.zip(strips) {
+            // The length only matters to how the frame is split up
+            let same_length = StripConfig { led_count: current.led_count, ..*strip };
+            if same_length != *current {
+                output.set_strip(*strip)?;
+            }
+            if strip.led_count != current.led_count {
+                *buffer = Segment::new(strip.led_count);
+            }
+            *current = *strip;
+        }
+
+        Ok(())
+    }
+
+    pub fn write(&mut self, frame: &Segment) -> Result<()> {
+        let mut colors = frame.leds().iter().map(|led| led.color());
+        for buffer in &mut self.buffers {
+            for led in buffer.leds_mut() {
+                led.set(colors.next().unwrap_or_default());
+            }
+        }
+
+        // Start every output before waiting on any so they transmit in parallel
+        for (output, buffer) in self.outputs.iter_mut().zip(&self.buffers) {
+            output.start(buffer)?;
+        }
+        for output in &mut self.outputs {
+            output.wait()?;
+        }
+
+        Ok(())
+    }
+}
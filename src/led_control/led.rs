@@ -12,7 +12,6 @@ impl Led {
         }
     }
 
-    #[allow(dead_code)]
     pub fn turn_off(&mut self) {
         self.color = Color::black();
     }
@@ -0,0 +1,404 @@
+use crate::effects::{Effect, EffectConfig, FrameContext, Layout};
+use super::matrix::MatrixConfig;
+use super::pixel_map::PixelMap;
+use super::segment::Segment;
+use super::transition::{Transition, TransitionConfig};
+
+use anyhow::{Result, Error};
+use serde::{Serialize, Deserialize};
+
+
+pub const MAX_SEGMENTS: usize = 16;
+
+
+/// A named range of the strip running its own effect. Effects draw onto a virtual strip
+/// that is shorter than the range when pixels are grouped, spaced or mirrored
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SegmentConfig {
+    pub name: String,
+    /// First LED of the range
+    pub start: usize,
+    /// Runs to the end of the strip when not set
+    pub length: Option<usize>,
+    pub reversed: bool,
+    /// Draws the effect on the first half and mirrors it onto the second
+    pub mirrored: bool,
+    /// LEDs lit per effect pixel
+    pub grouping: usize,
+    /// LEDs left off after each group
+    pub spacing: usize,
+    pub brightness: u8,
+    /// Per channel (RGB) scale for this segment, on top of the output's white balance
+    pub white_balance: [u8; 3],
+    /// Lays the range out as a 2D panel, so effects draw on its width and height
+    pub matrix: Option<MatrixConfig>,
+    pub effect: EffectConfig,
+}
+
+impl SegmentConfig {
+    /// Checks the segment fits on a strip of `led_count`
+    pub fn validate(&self, led_count: usize) -> Result<()> {
+        if self.start >= led_count {
+            return Err(Error::msg(format!("Segment {} starts at {} past the end of {led_count} LEDs", self.name, self.start)))
+        }
+        if self.grouping == 0 || self.grouping > led_count {
+            return Err(Error::msg(format!("Segment {} needs a grouping between 1 and {led_count}", self.name)))
+        }
+        if self.spacing > led_count {
+            return Err(Error::msg(format!("Segment {} spacing {} over maximum of {led_count}", self.name, self.spacing)))
+        }
+        match &self.matrix {
+            Some(matrix) => matrix.validate(self.physical_length(led_count)),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks a whole layout. Segments may overlap, later ones are drawn on top
+    pub fn validate_all(segments: &[SegmentConfig], led_count: usize) -> Result<()> {
+        if segments.is_empty() || segments.len() > MAX_SEGMENTS {
+            return Err(Error::msg(format!("Need between 1 and {MAX_SEGMENTS} segments")))
+        }
+        segments.iter().try_for_each(|segment| segment.validate(led_count))
+    }
+
+    /// LEDs covered on a strip of `led_count`, clipped to the end of it
+    pub fn physical_length(&self, led_count: usize) -> usize {
+        let available = led_count.saturating_sub(self.start);
+        self.length.unwrap_or(available).min(available)
+    }
+
+    /// Number of pixels the effect draws
+    pub fn virtual_length(&self, led_count: usize) -> usize {
+        let groups = self.groups(led_count);
+        if self.mirrored {
+            (groups + 1) / 2
+        } else {
+            groups
+        }
+    }
+
+    /// What the effect draws on. A matrix takes its size from the panel rather than the range
+    pub fn layout(&self, led_count: usize) -> Layout {
+        match &self.matrix {
+            Some(matrix) => {
+                let (width, height) = matrix.size();
+                Layout { width, height }
+            },
+            None => Layout::strip(self.virtual_length(led_count)),
+        }
+    }
+
+    fn groups(&self, led_count: usize) -> usize {
+        let step = self.grouping + self.spacing;
+        (self.physical_length(led_count) + step - 1) / step
+    }
+}
+
+impl Default for SegmentConfig {
+    fn default() -> Self {
+        Self {
+            name: "main".to_string(),
+            start: 0,
+            length: None,
+            reversed: false,
+            mirrored: false,
+            grouping: 1,
+            spacing: 0,
+            brightness: 255,
+            white_balance: [255; 3],
+            matrix: None,
+            effect: EffectConfig::default(),
+        }
+    }
+}
+
+
+/// Runtime state of a segment: its effect, the virtual strip it draws on and any
+/// transition between effects
+pub struct LogicalSegment {
+    config: SegmentConfig,
+    led_count: usize,
+    effect: Box<dyn Effect + Send>,
+    buffer: Segment,
+    shown: Segment,
+    transition: Option<Transition>,
+    /// Effect pixel for each position along a matrix's wiring, row by row
+    wiring: Option<Vec<Option<usize>>>,
+    /// Position of each effect pixel
+    map: PixelMap,
+}
+
+impl LogicalSegment {
+    /// `map` holds the position of every LED on the strip. Without one, effect pixels are
+    /// laid out one unit apart along the strip or across the matrix
+    pub fn new(config: SegmentConfig, led_count: usize, map: Option<&PixelMap>) -> Result<Self> {
+        let layout = config.layout(led_count);
+        let wiring = config.matrix.as_ref().map(wiring);
+        let map = match map {
+            Some(map) => segment_map(&config, led_count, wiring.as_deref(), map),
+            None => PixelMap::grid(layout.width, layout.height),
+        };
+
+        Ok(Self {
+            effect: config.effect.build(layout, &map)?,
+            buffer: Segment::new(layout.led_count()),
+            shown: Segment::new(layout.led_count()),
+            transition: None,
+            wiring,
+            map,
+            config,
+            led_count,
+        })
+    }
+
+    pub fn config(&self) -> &SegmentConfig {
+        &self.config
+    }
+
+    /// Crossfades from the current effect into `effect`
+    pub fn set_effect(&mut self, effect: EffectConfig, transition: TransitionConfig) -> Result<()> {
+        let layout = self.config.layout(self.led_count);
+        let length = layout.led_count();
+        let new_effect = effect.build(layout, &self.map)?;
+
+        // Effects only draw the LEDs they use, so start the new one on a clean buffer
+        let old_effect = std::mem::replace(&mut self.effect, new_effect);
+        let old_buffer = std::mem::replace(&mut self.buffer, Segment::new(length));
+        self.transition = Some(match self.transition.take() {
+            // Fade from the half-blended frame on screen, the old effect alone would jump
+            Some(_) => {
+                log::info!("Cutting short previous transition on {}", self.config.name);
+                Transition::from_frame(self.shown.clone(), transition)
+            },
+            None => Transition::new(old_effect, old_buffer, transition),
+        });
+        self.config.effect = effect;
+
+        Ok(())
+    }
+
+    /// Starts the effect over from scratch
+    pub fn restart(&mut self) -> Result<()> {
+        let layout = self.config.layout(self.led_count);
+        self.effect = self.config.effect.build(layout, &self.map)?;
+        self.buffer = Segment::new(layout.led_count());
+        self.shown = Segment::new(layout.led_count());
+        self.transition = None;
+        Ok(())
+    }
+
+    pub fn tick(&mut self, ctx: &mut FrameContext, frame: &mut Segment) -> Result<()> {
+        self.effect.tick(ctx, &mut self.buffer)?;
+
+        match &mut self.transition {
+            Some(transition) => {
+                if transition.tick(ctx, &self.buffer, &mut self.shown)? {
+                    self.transition = None;
+                }
+            },
+            None => self.shown.clone_from(&self.buffer),
+        }
+
+        self.draw(frame);
+        Ok(())
+    }
+
+    /// Maps the virtual strip onto the segment's range of `frame`
+    fn draw(&self, frame: &mut Segment) {
+        let config = &self.config;
+        let length = config.physical_length(self.led_count);
+        let scale = config.brightness as f32 / 255.0;
+        // White balance tints RGB, the white die is only dimmed
+        let [r, g, b] = config.white_balance.map(|level| level as f32 / 255.0 * scale);
+        let scale = [r, g, b, scale];
+
+        // Presets are only checked against the longest strips there can be, so the segment
+        // may start past the end. Its length is 0 then
+        let start = config.start.min(self.led_count);
+        let range = start..start + length;
+        for (offset, led) in frame.leds_mut()[range].iter_mut().enumerate() {
+            if let Some(pixel) = effect_pixel(config, self.led_count, self.wiring.as_deref(), offset) {
+                led.set(self.shown.leds()[pixel].color().scale_channels(scale));
+            }
+        }
+    }
+}
+
+
+/// The effect pixel shown by the LED `offset` into the segment. None for LEDs left off
+/// by spacing or past the end of a matrix
+fn effect_pixel(config: &SegmentConfig, led_count: usize, wiring: Option<&[Option<usize>]>, offset: usize) -> Option<usize> {
+    let step = config.grouping + config.spacing;
+    let groups = config.groups(led_count);
+    if offset % step >= config.grouping {
+        return None
+    }
+
+    let mut group = offset / step;
+    if config.reversed {
+        group = groups - 1 - group;
+    }
+    if config.mirrored && group >= (groups + 1) / 2 {
+        group = groups - 1 - group;
+    }
+
+    match wiring {
+        Some(wiring) => wiring.get(group).copied().flatten(),
+        None => Some(group),
+    }
+}
+
+
+/// Positions of the effect pixels, each taken from the first LED that shows it. Pixels no
+/// LED shows, or whose LED isn't in `map`, sit at the origin
+fn segment_map(config: &SegmentConfig, led_count: usize, wiring: Option<&[Option<usize>]>, map: &PixelMap) -> PixelMap {
+    let layout = config.layout(led_count);
+    let mut points = vec![None; layout.led_count()];
+    for offset in 0..config.physical_length(led_count) {
+        if let Some(pixel) = effect_pixel(config, led_count, wiring, offset) {
+            if points[pixel].is_none() {
+                points[pixel] = map.get(config.start + offset);
+            }
+        }
+    }
+    PixelMap::new(points.into_iter().map(Option::unwrap_or_default).collect())
+}
+
+
+/// Inverts the matrix mapping so each wired pixel can look up what the effect drew for it
+fn wiring(matrix: &MatrixConfig) -> Vec<Option<usize>> {
+    let (width, height) = matrix.size();
+    let mut wiring = vec![None; width * height];
+    for y in 0..height {
+        for x in 0..width {
+            if let Some(index) = matrix.index(x, y) {
+                wiring[index] = Some(y * width + x);
+            }
+        }
+    }
+    wiring
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::effects::EffectRng;
+    use crate::led_control::Color;
+    use crate::led_control::transition::Curve;
+    use rand::SeedableRng;
+    use std::time::{Duration, SystemTime};
+
+    /// Fills the whole segment with one color
+    struct Fill(Color);
+
+    impl Effect for Fill {
+        fn tick(&mut self, _ctx: &mut FrameContext, segment: &mut Segment) -> Result<()> {
+            segment.leds_mut().iter_mut().for_each(|led| led.set(self.0));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn rejects_segments_off_the_strip() {
+        let segment = |start, grouping, spacing| SegmentConfig { start, grouping, spacing, ..Default::default() };
+
+        assert!(segment(0, 1, 0).validate(100).is_ok());
+        assert!(segment(99, 100, 100).validate(100).is_ok());
+        assert!(segment(100, 1, 0).validate(100).is_err());
+        assert!(segment(0, 0, 0).validate(100).is_err());
+        assert!(segment(0, 101, 0).validate(100).is_err());
+        assert!(segment(0, 1, usize::MAX).validate(100).is_err());
+        assert!(segment(0, usize::MAX, 1).validate(100).is_err());
+    }
+
+    #[test]
+    fn maps_pixels_to_the_first_led_showing_them() {
+        let map = PixelMap::grid(10, 1);
+        let xs = |config: &SegmentConfig| segment_map(config, 10, None, &map).points().iter().map(|point| point.x).collect::<Vec<_>>();
+
+        let reversed = SegmentConfig { start: 2, length: Some(6), grouping: 2, reversed: true, ..Default::default() };
+        assert_eq!(xs(&reversed), vec![6.0, 4.0, 2.0]);
+
+        let spaced = SegmentConfig { length: Some(4), spacing: 1, ..Default::default() };
+        assert_eq!(xs(&spaced), vec![0.0, 2.0]);
+
+        let mirrored = SegmentConfig { length: Some(5), mirrored: true, ..Default::default() };
+        assert_eq!(xs(&mirrored), vec![0.0, 1.0, 2.0]);
+    }
+
+    #[test]
+    fn tints_with_the_white_balance() -> Result<()> {
+        let config = SegmentConfig { brightness: 128, white_balance: [255, 224, 140], ..Default::default() };
+        let mut segment = LogicalSegment::new(config, 1, None)?;
+        segment.shown.leds_mut()[0].set(Color::rgbw(200, 200, 200, 100));
+        let mut frame = Segment::new(1);
+
+        segment.draw(&mut frame);
+        let color = frame.leds()[0].color();
+        assert_eq!([color.r, color.g, color.b, color.w], [100, 88, 55, 50]);
+        Ok(())
+    }
+
+    #[test]
+    fn cut_short_transitions_continue_from_the_shown_frame() -> Result<()> {
+        let linear = TransitionConfig { duration_ms: 100, curve: Curve::Linear };
+        let mut segment = LogicalSegment::new(SegmentConfig::default(), 1, None)?;
+        segment.effect = Box::new(Fill(Color::rgb(200, 0, 0)));
+        let mut frame = Segment::new(1);
+        let mut rng = EffectRng::seed_from_u64(0);
+        let mut ctx = FrameContext {
+            frame: 0,
+            delta: Duration::from_millis(50),
+            elapsed: Duration::from_millis(50),
+            time: SystemTime::UNIX_EPOCH,
+            rng: &mut rng,
+        };
+        let shown = |frame: &Segment| {
+            let color = frame.leds()[0].color();
+            [color.r, color.g, color.b]
+        };
+
+        segment.tick(&mut ctx, &mut frame)?;
+        segment.set_effect(EffectConfig::default(), linear)?;
+        segment.effect = Box::new(Fill(Color::rgb(0, 200, 0)));
+        segment.tick(&mut ctx, &mut frame)?;
+        assert_eq!(shown(&frame), [100, 100, 0]);
+
+        // Halfway from red to green, a second change fades on from there rather than from green
+        segment.set_effect(EffectConfig::default(), linear)?;
+        segment.effect = Box::new(Fill(Color::rgb(0, 0, 200)));
+        ctx.delta = Duration::from_millis(10);
+        segment.tick(&mut ctx, &mut frame)?;
+        assert_eq!(shown(&frame), [90, 90, 20]);
+
+        ctx.delta = Duration::from_millis(90);
+        segment.tick(&mut ctx, &mut frame)?;
+        assert_eq!(shown(&frame), [0, 0, 200]);
+        Ok(())
+    }
+
+    #[test]
+    fn draws_nothing_when_starting_past_the_end() -> Result<()> {
+        // A preset saved for longer strips
+        let config = SegmentConfig { start: 80, ..Default::default() };
+        let mut segment = LogicalSegment::new(config, 50, Some(&PixelMap::grid(50, 1)))?;
+        let mut frame = Segment::new(50);
+        let mut rng = EffectRng::seed_from_u64(0);
+        let mut ctx = FrameContext {
+            frame: 0,
+            delta: Duration::from_millis(10),
+            elapsed: Duration::from_millis(10),
+            time: SystemTime::UNIX_EPOCH,
+            rng: &mut rng,
+        };
+
+        segment.tick(&mut ctx, &mut frame)?;
+        assert!(frame.leds().iter().all(|led| {
+            let color = led.color();
+            (color.r, color.g, color.b) == (0, 0, 0)
+        }));
+        Ok(())
+    }
+}
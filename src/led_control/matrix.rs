@@ -0,0 +1,170 @@
+use super::color::Color;
+
+use anyhow::{Result, Error};
+use serde::{Serialize, Deserialize};
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatrixOrder {
+    /// The wiring runs along each row before moving to the next
+    #[default]
+    RowMajor,
+    ColumnMajor,
+}
+
+
+/// How a grid of pixels is wired, so effects can draw in (x, y) with (0, 0) at the top
+/// left no matter how the panel is mounted
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MatrixConfig {
+    /// Size of the panel as wired, before rotation
+    pub width: usize,
+    pub height: usize,
+    pub order: MatrixOrder,
+    /// Every other row (or column) runs backwards, as on most zigzag wired panels
+    pub serpentine: bool,
+    /// How far the panel is turned clockwise from how it is wired, in degrees. One of 0,
+    /// 90, 180 or 270
+    pub rotation: u16,
+    pub flip_x: bool,
+    pub flip_y: bool,
+}
+
+impl MatrixConfig {
+    /// Checks the panel fits in `led_count` LEDs
+    pub fn validate(&self, led_count: usize) -> Result<()> {
+        if self.width == 0 || self.height == 0 {
+            return Err(Error::msg("Matrix needs a width and height"))
+        }
+        if self.width.checked_mul(self.height).map_or(true, |size| size > led_count) {
+            return Err(Error::msg(format!("{}x{} matrix is bigger than its {led_count} LEDs", self.width, self.height)))
+        }
+        if ![0, 90, 180, 270].contains(&self.rotation) {
+            return Err(Error::msg(format!("Rotation of {} isn't a multiple of 90 degrees", self.rotation)))
+        }
+        Ok(())
+    }
+
+    /// Size effects draw at, after rotation
+    pub fn size(&self) -> (usize, usize) {
+        match self.rotation {
+            90 | 270 => (self.height, self.width),
+            _ => (self.width, self.height),
+        }
+    }
+
+    /// Position along the wiring of the pixel at (x, y)
+    pub fn index(&self, x: usize, y: usize) -> Option<usize> {
+        let (width, height) = self.size();
+        if x >= width || y >= height {
+            return None
+        }
+
+        let x = if self.flip_x { width - 1 - x } else { x };
+        let y = if self.flip_y { height - 1 - y } else { y };
+
+        // Back to panel coordinates
+        let (x, y) = match self.rotation {
+            90 => (y, self.height - 1 - x),
+            180 => (self.width - 1 - x, self.height - 1 - y),
+            270 => (self.width - 1 - y, x),
+            _ => (x, y),
+        };
+
+        Some(match self.order {
+            MatrixOrder::RowMajor => {
+                let x = if self.serpentine && y % 2 == 1 { self.width - 1 - x } else { x };
+                y * self.width + x
+            },
+            MatrixOrder::ColumnMajor => {
+                let y = if self.serpentine && x % 2 == 1 { self.height - 1 - y } else { y };
+                x * self.height + y
+            },
+        })
+    }
+}
+
+impl Default for MatrixConfig {
+    fn default() -> Self {
+        Self {
+            width: 16,
+            height: 16,
+            order: MatrixOrder::default(),
+            serpentine: true,
+            rotation: 0,
+            flip_x: false,
+            flip_y: false,
+        }
+    }
+}
+
+
+/// 2D pixel buffer that matrix effects draw on, stored row by row
+#[derive(Clone)]
+pub struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+}
+
+impl Canvas {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![Color::black(); width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Black outside the canvas
+    #[allow(dead_code)] // For effects, the built-in ones only draw
+    pub fn get(&self, x: usize, y: usize) -> Color {
+        if x < self.width && y < self.height {
+            self.pixels[y * self.width + x]
+        } else {
+            Color::black()
+        }
+    }
+
+    /// Does nothing outside the canvas, so effects can draw shapes that run off the edge
+    pub fn set(&mut self, x: usize, y: usize, color: Color) {
+        if x < self.width && y < self.height {
+            self.pixels[y * self.width + x] = color;
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn fill(&mut self, color: Color) {
+        self.pixels.fill(color);
+    }
+
+    pub fn pixels(&self) -> &[Color] {
+        &self.pixels
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn must_fit_its_leds() {
+        let matrix = |width, height| MatrixConfig { width, height, ..Default::default() };
+
+        assert!(matrix(16, 16).validate(256).is_ok());
+        assert!(matrix(16, 17).validate(256).is_err());
+        assert!(matrix(0, 16).validate(256).is_err());
+        assert!(matrix(usize::MAX, 2).validate(256).is_err());
+    }
+}
@@ -1,11 +1,45 @@
+#[cfg(test)]
+mod capture;
+mod chipset;
 mod color;
 mod controller;
+mod group;
 mod led;
+mod logical;
+mod matrix;
+mod output;
+mod pipeline;
+mod pixel_map;
+mod power;
+mod preset;
+mod realtime;
+#[cfg(target_os = "espidf")]
+mod rmt;
 mod segment;
+#[cfg(target_os = "espidf")]
+mod spi;
+mod transition;
 
-pub use controller::LEDControllerService;
+pub use controller::{LEDControllerService, ControllerCommand, DeviceState, REFRESH_RATE};
 pub use segment::Segment;
-pub use led::Led;
+pub use logical::{SegmentConfig, MAX_SEGMENTS};
+pub use matrix::{Canvas, MatrixConfig};
+#[cfg(test)]
+pub use capture::CaptureOutput;
+#[cfg(test)]
+pub use chipset::Chipset;
+pub use chipset::StripConfig;
 pub use color::Color;
-
-const LED_COUNT: usize = 150;
\ No newline at end of file
+pub use output::Output;
+pub use pipeline::OutputConfig;
+pub use pixel_map::{PixelMap, Point};
+pub use power::PowerStatus;
+#[cfg(target_os = "espidf")]
+pub use preset::{Playlist, Preset};
+pub use preset::{PlaylistStatus, PresetLibrary};
+pub use realtime::{RealtimeSource, RealtimeUpdate};
+#[cfg(target_os = "espidf")]
+pub use rmt::RmtOutput;
+#[cfg(target_os = "espidf")]
+pub use spi::SpiOutput;
+pub use transition::TransitionConfig;
@@ -0,0 +1,46 @@
+use super::segment::Segment;
+use super::chipset::StripConfig;
+
+use anyhow::Result;
+
+
+/// Destination for finished frames, e.g. the RMT peripheral or the host simulator
+pub trait Output {
+    fn write(&mut self, frame: &Segment) -> Result<()>;
+
+    /// Begins sending a frame without waiting for it to go out, so several outputs can
+    /// transmit at once. Outputs that can't send in the background just write it
+    fn start(&mut self, frame: &Segment) -> Result<()> {
+        self.write(frame)
+    }
+
+    /// Waits for the frame from `start` to finish
+    fn wait(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Switches the chip type the frames are encoded for. Outputs that show colors directly
+    /// can ignore it
+    fn set_strip(&mut self, _strip: StripConfig) -> Result<()> {
+        Ok(())
+    }
+}
+
+
+impl<T: Output + ?Sized> Output for Box<T> {
+    fn write(&mut self, frame: &Segment) -> Result<()> {
+        (**self).write(frame)
+    }
+
+    fn start(&mut self, frame: &Segment) -> Result<()> {
+        (**self).start(frame)
+    }
+
+    fn wait(&mut self) -> Result<()> {
+        (**self).wait()
+    }
+
+    fn set_strip(&mut self, strip: StripConfig) -> Result<()> {
+        (**self).set_strip(strip)
+    }
+}
@@ -0,0 +1,169 @@
+use super::color::Color;
+use super::segment::Segment;
+use super::power::{PowerConfig, PowerStatus};
+use super::chipset::StripConfig;
+use super::pixel_map::PixelMap;
+
+use anyhow::{Result, Error};
+use serde::{Serialize, Deserialize};
+
+
+const GAMMA_RANGE: std::ops::RangeInclusive<f32> = 0.1..=5.0;
+const MAX_STRIPS: usize = 8; // One per RMT channel
+
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutputConfig {
+    /// Physical strips, each taking the next `led_count` pixels of the effect
+    pub strips: Vec<StripConfig>,
+    /// Global brightness, applied before gamma so steps look even
+    pub brightness: u8,
+    /// Per channel (RGB) gamma. 1.0 is linear, 2.2 to 2.8 is typical for perceptual output
+    pub gamma: [f32; 3],
+    /// Per channel (RGB) scale for the strip's white point. 255 on all channels disables it
+    pub white_balance: [u8; 3],
+    pub power: PowerConfig,
+}
+
+impl OutputConfig {
+    /// Every strip's frame is encoded at once to send them in parallel, and a pixel map has to
+    /// cover them all
+    pub const MAX_LED_COUNT: usize = PixelMap::MAX_POINTS;
+
+    pub fn validate(&self) -> Result<()> {
+        if self.strips.is_empty() || self.strips.len() > MAX_STRIPS {
+            return Err(Error::msg(format!("Need between 1 and {MAX_STRIPS} strips")))
+        }
+        for (i, strip) in self.strips.iter().enumerate() {
+            if self.strips[..i].iter().any(|other| other.pin == strip.pin) {
+                return Err(Error::msg(format!("Pin {} is used by more than one strip", strip.pin)))
+            }
+            strip.validate()?;
+        }
+        if self.strips.iter().filter(|strip| strip.chipset.clocked()).count() > 1 {
+            return Err(Error::msg("Only one strip can use a clocked chipset"))
+        }
+        if self.led_count() > Self::MAX_LED_COUNT {
+            return Err(Error::msg(format!("{} LEDs over maximum of {} across all strips", self.led_count(), Self::MAX_LED_COUNT)))
+        }
+
+        if let Some(gamma) = self.gamma.iter().find(|gamma| !GAMMA_RANGE.contains(gamma)) {
+            return Err(Error::msg(format!("Gamma {gamma} not in range {GAMMA_RANGE:?}")))
+        }
+        self.power.validate()
+    }
+
+    /// Total length of the logical pixel space
+    pub fn led_count(&self) -> usize {
+        self.strips.iter().map(|strip| strip.led_count).sum()
+    }
+
+    /// Whether going from `self` to `other` needs the outputs set up again at boot. Lengths
+    /// and chipsets of the same kind can be changed on the fly
+    pub fn needs_restart(&self, other: &OutputConfig) -> bool {
+        self.strips.len() != other.strips.len()
+            || self.strips.iter().zip(&other.strips)
+                .any(|(a, b)| a.pin != b.pin || a.chipset.clocked() != b.chipset.clocked())
+    }
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self {
+            strips: vec![StripConfig::default()],
+            brightness: 128,
+            gamma: [1.0; 3],
+            white_balance: [255; 3],
+            power: PowerConfig::default(),
+        }
+    }
+}
+
+
+/// Final stage before encoding. Brightness, gamma and white balance are folded into one
+/// lookup table per channel so each frame only costs a table lookup per channel. The
+/// power limit is applied last since it depends on what is actually sent to the strip
+pub struct OutputPipeline {
+    config: OutputConfig,
+    luts: [[u8; 256]; 4],
+}
+
+impl OutputPipeline {
+    pub fn new(config: OutputConfig) -> Self {
+        let mut pipeline = Self {
+            config,
+            luts: [[0; 256]; 4],
+        };
+        pipeline.build_luts();
+        pipeline
+    }
+
+    pub fn config(&self) -> &OutputConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: OutputConfig) {
+        self.config = config;
+        self.build_luts();
+    }
+
+    pub fn apply(&self, strip: &StripConfig, color: Color) -> Color {
+        // White either gets its own die or is mixed into RGB before correction
+        let color = if strip.chipset.channels() == 4 {
+            color.extract_white(strip.white_mode)
+        } else {
+            color.fold_white()
+        };
+
+        Color::rgbw(
+            self.luts[0][color.r as usize],
+            self.luts[1][color.g as usize],
+            self.luts[2][color.b as usize],
+            self.luts[3][color.w as usize],
+        )
+    }
+
+    pub fn apply_to(&self, frame: &mut Segment) -> PowerStatus {
+        let mut leds = frame.leds_mut().iter_mut();
+        for strip in &self.config.strips {
+            for led in leds.by_ref().take(strip.led_count) {
+                led.set(self.apply(strip, led.color()));
+            }
+        }
+
+        self.config.power.limit(frame)
+    }
+
+    fn build_luts(&mut self) {
+        let brightness = self.config.brightness as f32 / 255.0;
+
+        // The white die is already white, so it only follows the average gamma
+        let white_gamma = self.config.gamma.iter().sum::<f32>() / 3.0;
+
+        for (channel, lut) in self.luts.iter_mut().enumerate() {
+            let gamma = self.config.gamma.get(channel).copied().unwrap_or(white_gamma);
+            let white_balance = self.config.white_balance.get(channel).copied().unwrap_or(255) as f32 / 255.0;
+
+            for (input, output) in lut.iter_mut().enumerate() {
+                let level = (input as f32 / 255.0 * brightness).powf(gamma);
+                *output = (level * white_balance * 255.0).round() as u8;
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_total_led_count() {
+        let strip = |pin, led_count| StripConfig { pin, led_count, ..Default::default() };
+        let config = |strips| OutputConfig { strips, ..Default::default() };
+
+        assert!(config(vec![strip(15, 512), strip(16, 512)]).validate().is_ok());
+        assert!(config(vec![strip(15, 512), strip(16, 513)]).validate().is_err());
+    }
+}
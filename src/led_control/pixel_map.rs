@@ -0,0 +1,188 @@
+use anyhow::{Result, Error};
+use serde::{Serialize, Deserialize};
+
+
+/// Position of one pixel, in whatever units the map was measured in
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(from = "Coordinates", into = "Coordinates")]
+pub struct Point {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Point {
+    pub fn new(x: f32, y: f32, z: f32) -> Self {
+        Self { x, y, z }
+    }
+
+    pub fn distance(&self, other: &Point) -> f32 {
+        ((self.x - other.x).powi(2) + (self.y - other.y).powi(2) + (self.z - other.z).powi(2)).sqrt()
+    }
+}
+
+/// Points are written as `[x, y]` or `[x, y, z]`
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Coordinates {
+    Flat([f32; 2]),
+    Full([f32; 3]),
+}
+
+impl From<Coordinates> for Point {
+    fn from(coordinates: Coordinates) -> Self {
+        match coordinates {
+            Coordinates::Flat([x, y]) => Point::new(x, y, 0.0),
+            Coordinates::Full([x, y, z]) => Point::new(x, y, z),
+        }
+    }
+}
+
+impl From<Point> for Coordinates {
+    fn from(point: Point) -> Self {
+        Coordinates::Full([point.x, point.y, point.z])
+    }
+}
+
+
+/// Where each pixel physically is, for installations that aren't a straight strip or a
+/// regular panel. Spatial effects draw from these instead of from pixel indices
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct PixelMap {
+    points: Vec<Point>,
+}
+
+impl PixelMap {
+    /// About what fits in NVS
+    pub const MAX_POINTS: usize = 1024;
+
+    pub fn new(points: Vec<Point>) -> Self {
+        Self { points }
+    }
+
+    /// Pixels laid out row by row, one unit apart. A strip is a single row
+    pub fn grid(width: usize, height: usize) -> Self {
+        Self::new(
+            (0..width * height)
+                .map(|i| Point::new((i % width) as f32, (i / width) as f32, 0.0))
+                .collect()
+        )
+    }
+
+    /// JSON list of `[x, y]` or `[x, y, z]` points, one per pixel
+    pub fn from_json(data: &[u8]) -> Result<Self> {
+        Ok(serde_json::from_slice(data)?)
+    }
+
+    /// One `x,y` or `x,y,z` line per pixel. Blank lines, lines starting with `#` and a
+    /// header on the first line are skipped
+    pub fn from_csv(data: &str) -> Result<Self> {
+        let mut points = vec![];
+        let mut first = true;
+        for (number, line) in data.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue
+            }
+
+            let values = line.split(',').map(|value| value.trim().parse::<f32>()).collect::<Result<Vec<_>, _>>();
+            match values.as_deref() {
+                Ok([x, y]) => points.push(Point::new(*x, *y, 0.0)),
+                Ok([x, y, z]) => points.push(Point::new(*x, *y, *z)),
+                Err(_) if first => (), // Header
+                _ => return Err(Error::msg(format!("Line {} isn't x,y or x,y,z: '{line}'", number + 1))),
+            }
+            first = false;
+        }
+        Ok(Self::new(points))
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.points.is_empty() || self.points.len() > Self::MAX_POINTS {
+            return Err(Error::msg(format!("Need between 1 and {} points", Self::MAX_POINTS)))
+        }
+        if self.points.iter().any(|point| !(point.x.is_finite() && point.y.is_finite() && point.z.is_finite())) {
+            return Err(Error::msg("Points must be finite numbers"))
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn points(&self) -> &[Point] {
+        &self.points
+    }
+
+    pub fn get(&self, index: usize) -> Option<Point> {
+        self.points.get(index).copied()
+    }
+
+    /// Corners of the box holding every point
+    pub fn bounds(&self) -> (Point, Point) {
+        let mut min = self.points.first().copied().unwrap_or_default();
+        let mut max = min;
+        for point in &self.points {
+            min = Point::new(min.x.min(point.x), min.y.min(point.y), min.z.min(point.z));
+            max = Point::new(max.x.max(point.x), max.y.max(point.y), max.z.max(point.z));
+        }
+        (min, max)
+    }
+
+    pub fn center(&self) -> Point {
+        let (min, max) = self.bounds();
+        Point::new((min.x + max.x) / 2.0, (min.y + max.y) / 2.0, (min.z + max.z) / 2.0)
+    }
+
+    /// Points moved and scaled so the map's longest side runs from 0 to 1, keeping its
+    /// proportions. Lets effects use the same parameters whatever units the map is in
+    pub fn normalized(&self) -> Vec<Point> {
+        let (min, max) = self.bounds();
+        let size = (max.x - min.x).max(max.y - min.y).max(max.z - min.z);
+        let scale = if size > 0.0 { 1.0 / size } else { 1.0 };
+
+        self.points.iter()
+            .map(|point| Point::new((point.x - min.x) * scale, (point.y - min.y) * scale, (point.z - min.z) * scale))
+            .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_csv() -> Result<()> {
+        let map = PixelMap::from_csv("x,y,z\n# Front\n0,0\n\n1.5, 2, 3\n")?;
+        assert_eq!(map.points(), &[Point::new(0.0, 0.0, 0.0), Point::new(1.5, 2.0, 3.0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn skips_only_one_csv_header() {
+        assert!(PixelMap::from_csv("x,y\nleft,right\n0,0\n").is_err());
+        assert!(PixelMap::from_csv("0,0\nx,y\n").is_err());
+        assert!(PixelMap::from_csv("0,0,0,0\n").is_err());
+    }
+
+    #[test]
+    fn reads_json() -> Result<()> {
+        let map = PixelMap::from_json(b"[[0, 1], [2, 3, 4]]")?;
+        assert_eq!(map.points(), &[Point::new(0.0, 1.0, 0.0), Point::new(2.0, 3.0, 4.0)]);
+        assert!(PixelMap::from_json(b"[[0]]").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn normalizes_to_the_longest_side() {
+        let map = PixelMap::new(vec![Point::new(-2.0, 1.0, 0.0), Point::new(2.0, 3.0, 0.0)]);
+        assert_eq!(map.normalized(), vec![Point::new(0.0, 0.0, 0.0), Point::new(1.0, 0.5, 0.0)]);
+
+        // A single point can't be scaled
+        let point = PixelMap::new(vec![Point::new(5.0, 5.0, 5.0)]);
+        assert_eq!(point.normalized(), vec![Point::new(0.0, 0.0, 0.0)]);
+    }
+}
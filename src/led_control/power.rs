@@ -0,0 +1,129 @@
+use super::segment::Segment;
+use super::color::Color;
+
+use anyhow::{Result, Error};
+use serde::{Serialize, Deserialize};
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PowerConfig {
+    /// Budget for the whole strip. 0 disables limiting
+    pub max_milliamps: u32,
+    /// Draw of one color channel at full brightness
+    pub milliamps_per_channel: f32,
+    /// Draw of each pixel when off
+    pub idle_milliamps: f32,
+}
+
+impl Default for PowerConfig {
+    fn default() -> Self {
+        Self {
+            max_milliamps: 0,
+            milliamps_per_channel: 20.0, // Typical for WS2811 pixels
+            idle_milliamps: 1.0,
+        }
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct PowerStatus {
+    /// Estimated draw of the frame before limiting
+    pub estimated_milliamps: u32,
+    /// Scale applied to keep within budget. 1.0 when not limited
+    pub scale: f32,
+}
+
+impl Default for PowerStatus {
+    fn default() -> Self {
+        Self {
+            estimated_milliamps: 0,
+            scale: 1.0,
+        }
+    }
+}
+
+
+impl PowerConfig {
+    pub fn validate(&self) -> Result<()> {
+        // Written so NaN fails the checks too
+        let per_channel_ok = self.milliamps_per_channel > 0.0 && self.milliamps_per_channel.is_finite();
+        let idle_ok = self.idle_milliamps >= 0.0 && self.idle_milliamps.is_finite();
+        if !per_channel_ok || !idle_ok {
+            return Err(Error::msg("Power draw per channel must be positive and idle draw can't be negative"))
+        }
+        Ok(())
+    }
+
+    pub fn estimate_milliamps(&self, frame: &Segment) -> f32 {
+        let channel_total: u32 = frame.leds().iter()
+            .map(|led| led.color())
+            .map(|color| color.r as u32 + color.g as u32 + color.b as u32 + color.w as u32)
+            .sum();
+
+        frame.leds().len() as f32 * self.idle_milliamps
+            + channel_total as f32 / 255.0 * self.milliamps_per_channel
+    }
+
+    /// Scales the frame down so its estimated draw stays within the budget
+    pub fn limit(&self, frame: &mut Segment) -> PowerStatus {
+        let estimate = self.estimate_milliamps(frame);
+        let idle = frame.leds().len() as f32 * self.idle_milliamps;
+        let budget = self.max_milliamps as f32;
+
+        let scale = if self.max_milliamps == 0 || estimate <= budget {
+            1.0
+        } else {
+            // Only the channel draw can be scaled, the idle draw is always there
+            ((budget - idle) / (estimate - idle)).max(0.0)
+        };
+
+        if scale < 1.0 {
+            for led in frame.leds_mut() {
+                let color = led.color();
+                // Round down so the result never goes over budget
+                led.set(Color::rgbw(
+                    (color.r as f32 * scale) as u8,
+                    (color.g as f32 * scale) as u8,
+                    (color.b as f32 * scale) as u8,
+                    (color.w as f32 * scale) as u8,
+                ));
+            }
+        }
+
+        PowerStatus {
+            estimated_milliamps: estimate.round() as u32,
+            scale,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_draw_that_isnt_a_number() {
+        for bad in [f32::NAN, f32::INFINITY, -1.0] {
+            assert!(PowerConfig { milliamps_per_channel: bad, ..Default::default() }.validate().is_err());
+            assert!(PowerConfig { idle_milliamps: bad, ..Default::default() }.validate().is_err());
+        }
+        assert!(PowerConfig { milliamps_per_channel: 0.0, ..Default::default() }.validate().is_err());
+        assert!(PowerConfig { idle_milliamps: 0.0, ..Default::default() }.validate().is_ok());
+    }
+
+    #[test]
+    fn limits_to_budget() {
+        let config = PowerConfig { max_milliamps: 500, ..Default::default() };
+        let mut frame = Segment::new(100);
+        for led in frame.leds_mut() {
+            led.set(Color::rgb(255, 255, 255));
+        }
+
+        let status = config.limit(&mut frame);
+        assert_eq!(status.estimated_milliamps, 6100);
+        assert!(config.estimate_milliamps(&frame) <= 500.0);
+    }
+}
@@ -0,0 +1,279 @@
+use crate::effects::EffectRng;
+use super::logical::SegmentConfig;
+use super::pipeline::OutputConfig;
+
+use anyhow::{Result, Error};
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use serde::{Serialize, Deserialize};
+use std::time::Duration;
+
+
+const MAX_PRESETS: usize = 16;
+const MAX_PLAYLISTS: usize = 8;
+const MAX_ENTRIES: usize = 32;
+
+
+/// Named snapshot of the segments, with their effects, and the brightness
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Preset {
+    pub name: String,
+    pub segments: Vec<SegmentConfig>,
+    pub brightness: u8,
+}
+
+impl Preset {
+    /// Checks the preset and fills in defaults for missing effect parameters
+    pub fn resolve(mut self) -> Result<Self> {
+        if self.name.is_empty() {
+            return Err(Error::msg("Preset needs a name"))
+        }
+        // The strips can change after the preset is saved, so only check it against the
+        // most LEDs there can be
+        SegmentConfig::validate_all(&self.segments, OutputConfig::MAX_LED_COUNT)?;
+        for segment in &mut self.segments {
+            segment.effect = segment.effect.clone().resolve()?;
+        }
+        Ok(self)
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PlaylistEntry {
+    /// Name of the preset to show
+    pub preset: String,
+    pub duration_ms: u64,
+}
+
+
+/// Presets shown one after the other
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Playlist {
+    pub name: String,
+    pub entries: Vec<PlaylistEntry>,
+    /// Plays the entries in a random order, shuffled again on every repeat
+    #[serde(default)]
+    pub shuffle: bool,
+    /// Starts over after the last entry instead of staying on it
+    #[serde(default)]
+    pub repeat: bool,
+}
+
+
+/// Saved presets and the playlists made from them
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PresetLibrary {
+    pub presets: Vec<Preset>,
+    pub playlists: Vec<Playlist>,
+}
+
+impl PresetLibrary {
+    /// Bytes of JSON the library can take up, so it still fits in NVS and can be read back
+    pub const MAX_SIZE: usize = 8000;
+
+    /// Checks every preset and that playlists only use presets that exist
+    pub fn resolve(mut self) -> Result<Self> {
+        if self.presets.len() > MAX_PRESETS || self.playlists.len() > MAX_PLAYLISTS {
+            return Err(Error::msg(format!("Up to {MAX_PRESETS} presets and {MAX_PLAYLISTS} playlists can be saved")))
+        }
+        self.presets = self.presets.into_iter().map(Preset::resolve).collect::<Result<_>>()?;
+        for playlist in &self.playlists {
+            self.validate_playlist(playlist)?;
+        }
+        self.check_size()?;
+        Ok(self)
+    }
+
+    fn check_size(&self) -> Result<()> {
+        let size = serde_json::to_vec(self)?.len();
+        if size > Self::MAX_SIZE {
+            return Err(Error::msg(format!("Presets and playlists would take {size} bytes, over the {} that can be saved", Self::MAX_SIZE)))
+        }
+        Ok(())
+    }
+
+    pub fn validate_playlist(&self, playlist: &Playlist) -> Result<()> {
+        if playlist.name.is_empty() {
+            return Err(Error::msg("Playlist needs a name"))
+        }
+        if playlist.entries.is_empty() || playlist.entries.len() > MAX_ENTRIES {
+            return Err(Error::msg(format!("Playlist {} needs between 1 and {MAX_ENTRIES} entries", playlist.name)))
+        }
+        for entry in &playlist.entries {
+            if self.preset(&entry.preset).is_none() {
+                return Err(Error::msg(format!("Playlist {} uses unknown preset {}", playlist.name, entry.preset)))
+            }
+            if entry.duration_ms == 0 {
+                return Err(Error::msg(format!("Playlist {} has an entry with no duration", playlist.name)))
+            }
+        }
+        Ok(())
+    }
+
+    pub fn preset(&self, name: &str) -> Option<&Preset> {
+        self.presets.iter().find(|preset| preset.name == name)
+    }
+
+    pub fn playlist(&self, name: &str) -> Option<&Playlist> {
+        self.playlists.iter().find(|playlist| playlist.name == name)
+    }
+
+    /// Adds the preset, or replaces the one with the same name. Leaves the library as it
+    /// was if the preset doesn't fit
+    pub fn save_preset(&mut self, preset: Preset) -> Result<()> {
+        let mut updated = self.clone();
+        match updated.presets.iter().position(|existing| existing.name == preset.name) {
+            Some(index) => updated.presets[index] = preset,
+            None if updated.presets.len() < MAX_PRESETS => updated.presets.push(preset),
+            None => return Err(Error::msg(format!("Up to {MAX_PRESETS} presets can be saved"))),
+        }
+        updated.check_size()?;
+        *self = updated;
+        Ok(())
+    }
+
+    /// Fails while a playlist still uses the preset
+    pub fn delete_preset(&mut self, name: &str) -> Result<()> {
+        if let Some(playlist) = self.playlists.iter().find(|playlist| playlist.entries.iter().any(|entry| entry.preset == name)) {
+            return Err(Error::msg(format!("Preset {name} is used by playlist {}", playlist.name)))
+        }
+        let count = self.presets.len();
+        self.presets.retain(|preset| preset.name != name);
+        if self.presets.len() == count {
+            return Err(Error::msg(format!("No preset {name}")))
+        }
+        Ok(())
+    }
+
+    /// Adds the playlist, or replaces the one with the same name. Leaves the library as it
+    /// was if the playlist doesn't fit
+    pub fn save_playlist(&mut self, playlist: Playlist) -> Result<()> {
+        self.validate_playlist(&playlist)?;
+        let mut updated = self.clone();
+        match updated.playlists.iter().position(|existing| existing.name == playlist.name) {
+            Some(index) => updated.playlists[index] = playlist,
+            None if updated.playlists.len() < MAX_PLAYLISTS => updated.playlists.push(playlist),
+            None => return Err(Error::msg(format!("Up to {MAX_PLAYLISTS} playlists can be saved"))),
+        }
+        updated.check_size()?;
+        *self = updated;
+        Ok(())
+    }
+
+    pub fn delete_playlist(&mut self, name: &str) -> Result<()> {
+        let count = self.playlists.len();
+        self.playlists.retain(|playlist| playlist.name != name);
+        if self.playlists.len() == count {
+            return Err(Error::msg(format!("No playlist {name}")))
+        }
+        Ok(())
+    }
+}
+
+
+/// What a playlist is currently showing
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PlaylistStatus {
+    pub playlist: String,
+    pub preset: String,
+    /// Index of the entry in the playlist
+    pub entry: usize,
+}
+
+
+/// Steps through a playlist as time passes
+pub struct PlaylistPlayer {
+    playlist: Playlist,
+    /// Entry indices in the order they are played
+    order: Vec<usize>,
+    position: usize,
+    remaining: Duration,
+    rng: EffectRng,
+}
+
+impl PlaylistPlayer {
+    pub fn new(playlist: Playlist) -> Self {
+        let mut player = Self {
+            order: (0..playlist.entries.len()).collect(),
+            playlist,
+            position: 0,
+            remaining: Duration::ZERO,
+            rng: EffectRng::from_entropy(),
+        };
+        player.restart();
+        player
+    }
+
+    pub fn status(&self) -> PlaylistStatus {
+        let entry = self.order[self.position];
+        PlaylistStatus {
+            playlist: self.playlist.name.clone(),
+            preset: self.playlist.entries[entry].preset.clone(),
+            entry,
+        }
+    }
+
+    /// The preset to switch to if the current entry has run its course. Stays on the last
+    /// entry of a playlist that doesn't repeat
+    pub fn tick(&mut self, delta: Duration) -> Option<&str> {
+        if self.remaining.is_zero() {
+            return None
+        }
+        self.remaining = self.remaining.saturating_sub(delta);
+        if !self.remaining.is_zero() {
+            return None
+        }
+
+        if self.position + 1 < self.order.len() {
+            self.position += 1;
+        } else if self.playlist.repeat {
+            self.restart();
+        } else {
+            return None
+        }
+        let entry = &self.playlist.entries[self.order[self.position]];
+        self.remaining = Duration::from_millis(entry.duration_ms);
+        Some(&entry.preset)
+    }
+
+    pub fn finished(&self) -> bool {
+        self.remaining.is_zero()
+    }
+
+    fn restart(&mut self) {
+        if self.playlist.shuffle {
+            self.order.shuffle(&mut self.rng);
+        }
+        self.position = 0;
+        self.remaining = Duration::from_millis(self.playlist.entries[self.order[0]].duration_ms);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_presets_that_dont_fit() {
+        let segments = vec![SegmentConfig::default(); 16];
+        let mut library = PresetLibrary::default();
+        let mut saved = 0;
+        for i in 0..MAX_PRESETS {
+            let before = library.clone();
+            let preset = Preset { name: format!("preset {i}"), segments: segments.clone(), brightness: 255 };
+            match library.save_preset(preset) {
+                Ok(_) => saved += 1,
+                Err(_) => {
+                    assert_eq!(library, before);
+                    break
+                },
+            }
+        }
+
+        assert!(saved > 0 && saved < MAX_PRESETS);
+        assert!(serde_json::to_vec(&library).unwrap().len() <= PresetLibrary::MAX_SIZE);
+    }
+}
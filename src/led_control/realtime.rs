@@ -0,0 +1,88 @@
+use super::color::Color;
+use super::segment::Segment;
+
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+
+/// Protocol a realtime stream arrived over
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RealtimeSource {
+    E131,
+    #[serde(rename = "artnet")]
+    ArtNet,
+    Ddp,
+    Wled,
+    Opc,
+}
+
+
+/// Pixels streamed in from outside, drawn instead of the effects
+pub struct RealtimeUpdate {
+    pub source: RealtimeSource,
+    /// First pixel written
+    pub offset: usize,
+    pub colors: Vec<Color>,
+    /// Shows everything written so far. Sources that sync their frames only set this once a
+    /// frame is complete
+    pub show: bool,
+    /// How long without updates before the effects take over again
+    pub timeout: Duration,
+}
+
+
+/// Frame built up from realtime updates
+pub struct RealtimeFrame {
+    source: RealtimeSource,
+    pending: Vec<Color>,
+    /// None until the first frame is complete, the effects carry on until then
+    shown: Option<Vec<Color>>,
+    timeout: Duration,
+    last_update: Instant,
+}
+
+impl RealtimeFrame {
+    pub fn new(source: RealtimeSource, led_count: usize) -> Self {
+        Self {
+            source,
+            pending: vec![Color::black(); led_count],
+            shown: None,
+            timeout: Duration::ZERO,
+            last_update: Instant::now(),
+        }
+    }
+
+    pub fn source(&self) -> RealtimeSource {
+        self.source
+    }
+
+    /// Pixels past the end of the strip are dropped
+    pub fn apply(&mut self, update: RealtimeUpdate) {
+        let start = update.offset.min(self.pending.len());
+        for (pixel, color) in self.pending[start..].iter_mut().zip(update.colors) {
+            *pixel = color;
+        }
+        if update.show {
+            self.shown = Some(self.pending.clone());
+        }
+
+        self.timeout = update.timeout;
+        self.last_update = Instant::now();
+    }
+
+    pub fn expired(&self) -> bool {
+        self.last_update.elapsed() > self.timeout
+    }
+
+    /// False if there is nothing to show yet
+    pub fn draw(&self, frame: &mut Segment) -> bool {
+        let Some(shown) = &self.shown else {
+            return false
+        };
+        for (led, color) in frame.leds_mut().iter_mut().zip(shown) {
+            led.set(*color);
+        }
+        true
+    }
+}
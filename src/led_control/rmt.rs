@@ -0,0 +1,154 @@
+use super::output::Output;
+use super::segment::Segment;
+use super::chipset::StripConfig;
+
+use anyhow::{Result, Error};
+use esp_idf_hal::{
+    rmt::{
+        Pulse,
+        PinState,
+        Signal,
+        VariableLengthSignal,
+        TxRmtDriver,
+        config::{Loop, TransmitConfig},
+        RmtChannel
+    },
+    peripheral,
+    gpio::OutputPin,
+    delay::BLOCK,
+};
+use esp_idf_sys::{esp, rmt_set_idle_level, rmt_write_items, rmt_wait_tx_done};
+
+use std::time::Duration;
+
+
+const CLOCK_DIV: u8 = 8; // 10MHz with an 80 MHz clock on the ESP32
+
+
+/// One-wire output through the ESP32 RMT peripheral
+pub struct RmtOutput<'a> {
+    rmt_tx: TxRmtDriver<'a>,
+    strip: StripConfig,
+    pulses_high: [Pulse; 2],
+    pulses_low: [Pulse; 2],
+    pulse_reset: [Pulse; 2],
+    // The driver keeps reading from the signal while it sends, so it lives here until `wait`
+    signal: VariableLengthSignal,
+    sending: bool,
+}
+
+
+impl<'a> RmtOutput<'a> {
+    /// There are 8 blocks of memory shared by all channels, and a channel given more than
+    /// one uses up those of the channels after it. More memory makes the output less
+    /// sensitive to interrupts from WiFi
+    pub fn new<C: RmtChannel>(
+        channel: impl peripheral::Peripheral<P = C> + 'a,
+        pin: impl peripheral::Peripheral<P = impl OutputPin> + 'a,
+        mem_blocks: u8,
+        strip: StripConfig,
+    ) -> Result<Self> {
+        let config = TransmitConfig {
+            clock_divider: CLOCK_DIV,
+            mem_block_num: mem_blocks,
+            carrier: None,
+            looping: Loop::None,
+            idle: Some(PinState::Low),
+            ..Default::default()
+        };
+
+        let rmt_tx = TxRmtDriver::new(channel, pin, &config)?;
+
+        let zero = Pulse::zero();
+        let mut output = Self {
+            rmt_tx,
+            strip,
+            pulses_high: [zero; 2],
+            pulses_low: [zero; 2],
+            pulse_reset: [zero; 2],
+            signal: VariableLengthSignal::new(),
+            sending: false,
+        };
+        output.set_strip(strip)?;
+
+        Ok(output)
+    }
+}
+
+
+impl Output for RmtOutput<'_> {
+    fn write(&mut self, frame: &Segment) -> Result<()> {
+        self.start(frame)?;
+        self.wait()
+    }
+
+    fn start(&mut self, frame: &Segment) -> Result<()> {
+        self.wait()?;
+
+        let data = self.strip.encode_frame(frame);
+        self.signal = VariableLengthSignal::new();
+
+        // MSB first
+        for val in data {
+            for shift in (0..8).rev() {
+                if val & (1 << shift) == 0 {
+                    self.signal.push(&self.pulses_low)?;
+                } else {
+                    self.signal.push(&self.pulses_high)?;
+                }
+            }
+        }
+
+        self.signal.push(&self.pulse_reset)?;
+        // The RMT only stops on an item with a zero duration. Without one in the stream the
+        // channel can run on into items left in its memory from the previous frame, which
+        // showed up as a flickering last pixel. The line then idles at the rest level
+        self.signal.push(&[Pulse::zero(), Pulse::zero()])?;
+
+        let items = self.signal.as_slice();
+        esp!(unsafe { rmt_write_items(self.rmt_tx.channel(), items.as_ptr(), items.len() as i32, false) })?;
+        self.sending = true;
+
+        Ok(())
+    }
+
+    fn wait(&mut self) -> Result<()> {
+        if self.sending {
+            esp!(unsafe { rmt_wait_tx_done(self.rmt_tx.channel(), BLOCK) })?;
+            self.sending = false;
+        }
+
+        Ok(())
+    }
+
+    fn set_strip(&mut self, strip: StripConfig) -> Result<()> {
+        let timing = strip.chipset.timing()
+            .ok_or_else(|| Error::msg(format!("{:?} needs a clock line, use an SPI output", strip.chipset)))?;
+        let hertz = self.rmt_tx.counter_clock()?;
+        let (active, rest) = if strip.chipset.inverted() {
+            (PinState::Low, PinState::High)
+        } else {
+            (PinState::High, PinState::Low)
+        };
+
+        self.pulses_high = [
+            Pulse::new_with_duration(hertz, active, &timing.t1h)?,
+            Pulse::new_with_duration(hertz, rest, &timing.t1l)?,
+        ];
+        self.pulses_low = [
+            Pulse::new_with_duration(hertz, active, &timing.t0h)?,
+            Pulse::new_with_duration(hertz, rest, &timing.t0l)?,
+        ];
+        self.pulse_reset = [
+            Pulse::new_with_duration(hertz, rest, &timing.reset)?,
+            Pulse::new_with_duration(hertz, rest, &Duration::from_micros(1))?,
+        ];
+
+        // The line has to rest at the reset level between frames
+        let idle = if strip.chipset.inverted() { 1 } else { 0 };
+        esp!(unsafe { rmt_set_idle_level(self.rmt_tx.channel(), true, idle) })?;
+
+        self.strip = strip;
+        Ok(())
+    }
+}
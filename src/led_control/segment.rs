@@ -1,6 +1,7 @@
 use super::led::Led;
 use super::color::Color;
 
+#[derive(Clone)]
 pub struct Segment {
     leds: Vec<Led>
 }
@@ -8,11 +9,10 @@ pub struct Segment {
 impl Segment {
     pub fn new(led_count: usize) -> Self {
         Self {
-            leds: vec![Led::new(); led_count+1] // BUG: Adding +1 to avoid last led from flickering
+            leds: vec![Led::new(); led_count]
         }
     }
 
-    #[allow(dead_code)]
     pub fn turn_off(&mut self) {
         for led in self.leds.iter_mut() {
             led.turn_off();
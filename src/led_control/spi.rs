@@ -0,0 +1,77 @@
+use super::output::Output;
+use super::segment::Segment;
+use super::chipset::StripConfig;
+
+use anyhow::{Result, Error};
+use esp_idf_hal::{
+    spi::{
+        SpiAnyPins,
+        SpiDriver,
+        SpiDeviceDriver,
+        Dma,
+        config::{Config, DriverConfig},
+    },
+    peripheral,
+    gpio::{OutputPin, AnyIOPin, AnyOutputPin},
+    units::Hertz,
+};
+
+
+// Large enough for a full frame in one transfer without gaps in the clock
+const DMA_BUFFER: usize = StripConfig::MAX_FRAME_SIZE;
+
+
+/// Two-wire clocked output (APA102, SK9822, WS2801) through an ESP32 SPI peripheral
+pub struct SpiOutput<'a> {
+    spi: SpiDeviceDriver<'a, SpiDriver<'a>>,
+    strip: StripConfig,
+}
+
+
+impl<'a> SpiOutput<'a> {
+    /// `baudrate` is the clock sent to the strip. WS2801 tops out around 1 MHz, APA102 and
+    /// SK9822 are usually fine up to 10 MHz on short wiring
+    pub fn new<S: SpiAnyPins>(
+        spi: impl peripheral::Peripheral<P = S> + 'a,
+        clock: impl peripheral::Peripheral<P = impl OutputPin> + 'a,
+        data: impl peripheral::Peripheral<P = impl OutputPin> + 'a,
+        baudrate: Hertz,
+        strip: StripConfig,
+    ) -> Result<Self> {
+        let driver = SpiDriver::new(
+            spi,
+            clock,
+            data,
+            Option::<AnyIOPin>::None,
+            &DriverConfig::new().dma(Dma::Auto(DMA_BUFFER)),
+        )?;
+        let spi = SpiDeviceDriver::new(driver, Option::<AnyOutputPin>::None, &Config::new().baudrate(baudrate))?;
+
+        let mut output = Self {
+            spi,
+            strip,
+        };
+        output.set_strip(strip)?;
+
+        Ok(output)
+    }
+}
+
+
+impl Output for SpiOutput<'_> {
+    fn write(&mut self, frame: &Segment) -> Result<()> {
+        let data = self.strip.encode_frame(frame);
+        self.spi.write(&data)?;
+
+        Ok(())
+    }
+
+    fn set_strip(&mut self, strip: StripConfig) -> Result<()> {
+        if !strip.chipset.clocked() {
+            return Err(Error::msg(format!("{:?} is one-wire, use an RMT output", strip.chipset)))
+        }
+
+        self.strip = strip;
+        Ok(())
+    }
+}
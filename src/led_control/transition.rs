@@ -0,0 +1,107 @@
+use crate::effects::{Effect, FrameContext};
+use super::segment::Segment;
+
+use anyhow::Result;
+use serde::{Serialize, Deserialize};
+use std::time::Duration;
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Curve {
+    Linear,
+    EaseIn,
+    EaseOut,
+    #[default]
+    EaseInOut,
+}
+
+impl Curve {
+    /// Maps linear progress `t` (0.0 to 1.0) onto the curve
+    pub fn apply(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Curve::Linear => t,
+            Curve::EaseIn => t * t,
+            Curve::EaseOut => t * (2.0 - t),
+            Curve::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TransitionConfig {
+    pub duration_ms: u64,
+    pub curve: Curve,
+}
+
+impl TransitionConfig {
+    pub fn duration(&self) -> Duration {
+        Duration::from_millis(self.duration_ms)
+    }
+}
+
+impl Default for TransitionConfig {
+    fn default() -> Self {
+        Self {
+            duration_ms: 1000,
+            curve: Curve::default(),
+        }
+    }
+}
+
+
+/// Keeps the outgoing effect running on its own buffer and crossfades it into the
+/// incoming effect until the configured duration has passed
+pub struct Transition {
+    /// None when fading from a held frame
+    effect: Option<Box<dyn Effect + Send>>,
+    segment: Segment,
+    config: TransitionConfig,
+    elapsed: Duration,
+}
+
+impl Transition {
+    pub fn new(effect: Box<dyn Effect + Send>, segment: Segment, config: TransitionConfig) -> Self {
+        Self {
+            effect: Some(effect),
+            segment,
+            config,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    /// Fades out of a still `frame`, such as a transition that was cut short
+    pub fn from_frame(frame: Segment, config: TransitionConfig) -> Self {
+        Self {
+            effect: None,
+            segment: frame,
+            config,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    /// Blends the outgoing effect with `incoming` into `frame`. Returns true once the
+    /// transition is complete and can be dropped
+    pub fn tick(&mut self, ctx: &mut FrameContext, incoming: &Segment, frame: &mut Segment) -> Result<bool> {
+        self.elapsed += ctx.delta;
+        let progress = if self.config.duration().is_zero() {
+            1.0
+        } else {
+            self.elapsed.as_secs_f32() / self.config.duration().as_secs_f32()
+        };
+        let t = self.config.curve.apply(progress);
+
+        if let Some(effect) = &mut self.effect {
+            effect.tick(ctx, &mut self.segment)?;
+        }
+
+        for ((out, old), new) in frame.leds_mut().iter_mut().zip(self.segment.leds()).zip(incoming.leds()) {
+            out.set(old.color().blend(new.color(), t));
+        }
+
+        Ok(progress >= 1.0)
+    }
+}
@@ -1,24 +1,49 @@
+
 use anyhow::Result;
-use esp_idf_hal::prelude::Peripherals;
+#[cfg(target_os = "espidf")]
+use anyhow::Error;
+#[cfg(target_os = "espidf")]
+use esp_idf_hal::{
+    prelude::Peripherals,
+    units::Hertz,
+    rmt::RMT,
+    spi::SPI2,
+    gpio::AnyOutputPin,
+};
+#[cfg(target_os = "espidf")]
 use esp_idf_svc::{
     eventloop::EspSystemEventLoop,
     nvs::EspDefaultNvsPartition,
 };
 
+#[cfg(target_os = "espidf")]
 use std:: {
     thread,
     time::Duration,
 };
 
 // If using the `binstart` feature of `esp-idf-sys`, always keep this module imported
+#[cfg(target_os = "espidf")]
 use esp_idf_sys as _;
 
 mod effects;
 mod led_control;
+#[cfg(target_os = "espidf")]
 mod ota;
+mod realtime;
+#[cfg(target_os = "espidf")]
 mod server;
+// Only the ESP32's server and NVS store use these, the simulator leaves them out
+#[cfg_attr(not(target_os = "espidf"), allow(dead_code))]
+mod settings;
+#[cfg(not(target_os = "espidf"))]
+mod sim;
+#[cfg(target_os = "espidf")]
 mod wifi;
+#[cfg_attr(not(target_os = "espidf"), allow(dead_code))]
+mod wled;
 
+#[cfg(target_os = "espidf")]
 fn main() -> Result<()> {
     // It is necessary to call this function once. Otherwise some patches to the runtime
     // implemented by esp-idf-sys might not link properly. See https://github.com/esp-rs/esp-idf-template/issues/71
@@ -33,16 +58,137 @@ fn main() -> Result<()> {
     let sysloop = EspSystemEventLoop::take()?;
     let nvs = EspDefaultNvsPartition::take()?;
 
-    let wifi_svc = wifi::WifiService::run_wifi_service(peripherals.modem, sysloop, nvs)?;
+    // Restored before anything is lit so the strips come up as they were left
+    let mut settings_store = settings::SettingsStore::new(nvs.clone())?;
+    let saved_settings = settings_store.load();
+    let saved_map = settings_store.load_map();
 
-    let _server = server::ServerService::init_server(wifi_svc)?;
+    let wifi_svc = wifi::WifiService::run_wifi_service(peripherals.modem, sysloop, nvs)?;
 
-    let _led_ctrl = led_control::LEDControllerService::init(
-        peripherals.rmt.channel0,
-        peripherals.pins.gpio15
+    // A config that can't be set up, e.g. one saved by firmware with other pin checks, mustn't
+    // leave the board dark and unreachable. It is checked before the peripherals are handed over
+    // since they can only be taken once
+    let saved_settings = match check_outputs(&saved_settings.output) {
+        Ok(_) => saved_settings,
+        Err(e) => {
+            log::error!("Unable to start the strips with the saved output config, using the defaults: {e:?}");
+            let output = led_control::OutputConfig::default();
+            let segments = match led_control::SegmentConfig::validate_all(&saved_settings.segments, output.led_count()) {
+                Ok(_) => saved_settings.segments,
+                Err(_) => settings::Settings::default().segments,
+            };
+            settings::Settings { output, segments, ..saved_settings }
+        }
+    };
+    let led_ctrl_svc = start_leds(peripherals.rmt, peripherals.spi2, &saved_settings, &saved_map)?;
+    let realtime_svc = realtime::RealtimeService::init(saved_settings.realtime.clone(), &led_ctrl_svc)?;
+    let settings_svc = settings::SettingsService::init(
+        settings_store,
+        saved_settings,
+        saved_map,
+        &led_ctrl_svc,
+        &realtime_svc,
     )?;
 
+    let _server = server::ServerService::init_server(wifi_svc, led_ctrl_svc, realtime_svc, settings_svc)?;
+
     loop {
         thread::sleep(Duration::from_secs(1000));
     }
 }
+
+/// Drives the strips with the saved settings
+#[cfg(target_os = "espidf")]
+fn start_leds(
+    rmt: RMT,
+    spi: SPI2,
+    settings: &settings::Settings,
+    map: &Option<led_control::PixelMap>,
+) -> Result<led_control::LEDControllerService> {
+    let led_outputs = build_outputs(rmt, spi, &settings.output.strips)?;
+    led_control::LEDControllerService::init(
+        led_outputs,
+        settings.segments.clone(),
+        settings.output.clone(),
+        map.clone(),
+        settings.library.clone(),
+    )
+}
+
+#[cfg(target_os = "espidf")]
+const SPI_CLOCK_PIN: i32 = 18;
+#[cfg(target_os = "espidf")]
+const SPI_BAUDRATE: Hertz = Hertz(4_000_000);
+
+#[cfg(target_os = "espidf")]
+type RmtFactory = Box<dyn FnOnce(AnyOutputPin, u8, led_control::StripConfig) -> Result<led_control::RmtOutput<'static>>>;
+
+/// Everything about the strips that would stop `build_outputs` from setting them up
+#[cfg(target_os = "espidf")]
+fn check_outputs(output: &led_control::OutputConfig) -> Result<()> {
+    output.validate()?;
+    if spi_clock_taken(&output.strips) {
+        return Err(Error::msg(format!("GPIO{SPI_CLOCK_PIN} is the SPI clock and can't also be a strip's data pin")))
+    }
+    Ok(())
+}
+
+/// Sets up an RMT channel for each one-wire strip and SPI for a clocked one, on the pins
+/// from the config, which has to have passed `check_outputs`
+#[cfg(target_os = "espidf")]
+fn build_outputs(
+    rmt: RMT,
+    spi: SPI2,
+    strips: &[led_control::StripConfig],
+) -> Result<Vec<Box<dyn led_control::Output + Send>>> {
+    use led_control::{RmtOutput, SpiOutput};
+
+    let RMT { channel0, channel1, channel2, channel3, channel4, channel5, channel6, channel7 } = rmt;
+    let channels: Vec<RmtFactory> = vec![
+        Box::new(move |pin, blocks, strip| RmtOutput::new(channel0, pin, blocks, strip)),
+        Box::new(move |pin, blocks, strip| RmtOutput::new(channel1, pin, blocks, strip)),
+        Box::new(move |pin, blocks, strip| RmtOutput::new(channel2, pin, blocks, strip)),
+        Box::new(move |pin, blocks, strip| RmtOutput::new(channel3, pin, blocks, strip)),
+        Box::new(move |pin, blocks, strip| RmtOutput::new(channel4, pin, blocks, strip)),
+        Box::new(move |pin, blocks, strip| RmtOutput::new(channel5, pin, blocks, strip)),
+        Box::new(move |pin, blocks, strip| RmtOutput::new(channel6, pin, blocks, strip)),
+        Box::new(move |pin, blocks, strip| RmtOutput::new(channel7, pin, blocks, strip)),
+    ];
+
+    // Spread the strips over the channels so each gets an even share of the RMT memory
+    let one_wire = strips.iter().filter(|strip| !strip.chipset.clocked()).count().max(1);
+    let mem_blocks = 8 / one_wire.min(8);
+    let mut channels = channels.into_iter().step_by(mem_blocks);
+    let mut spi = Some(spi);
+
+    strips.iter().map(|strip| {
+        // Pins come from the config so they can't be checked out of `Peripherals` by type.
+        // The config validation makes sure each is only used once
+        let pin = unsafe { AnyOutputPin::new(strip.pin as i32) };
+
+        let output: Box<dyn led_control::Output + Send> = if strip.chipset.clocked() {
+            let spi = spi.take().ok_or_else(|| Error::msg("Only one clocked strip is supported"))?;
+            let clock = unsafe { AnyOutputPin::new(SPI_CLOCK_PIN) };
+            Box::new(SpiOutput::new(spi, clock, pin, SPI_BAUDRATE, *strip)?)
+        } else {
+            let channel = channels.next().ok_or_else(|| Error::msg("Out of RMT channels"))?;
+            Box::new(channel(pin, mem_blocks as u8, *strip)?)
+        };
+
+        Ok(output)
+    }).collect()
+}
+
+#[cfg(target_os = "espidf")]
+fn spi_clock_taken(strips: &[led_control::StripConfig]) -> bool {
+    strips.iter().any(|strip| strip.chipset.clocked())
+        && strips.iter().any(|strip| strip.pin as i32 == SPI_CLOCK_PIN)
+}
+
+// Without an ESP32, run the effects in the host-side simulator
+#[cfg(not(target_os = "espidf"))]
+fn main() -> Result<()> {
+    env_logger::init();
+
+    sim::run()
+}
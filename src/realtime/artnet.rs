@@ -0,0 +1,333 @@
+use anyhow::{Result, Error};
+use serde::{Serialize, Deserialize};
+
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use super::{rgb_pixels, RealtimeConfig, RealtimeContext, UniverseLayout, POLL_INTERVAL};
+use crate::led_control::{RealtimeSource, RealtimeUpdate};
+
+
+pub const PORT: u16 = 6454;
+/// Largest 15 bit port-address
+const MAX_UNIVERSE: u16 = 0x7fff;
+/// Once an ArtSync has been seen, frames wait for the next one until they stop coming for
+/// this long
+const SYNC_TIMEOUT: Duration = Duration::from_secs(4);
+
+const MAX_PACKET_SIZE: usize = 530;
+const ARTNET_ID: &[u8] = b"Art-Net\0";
+const PROTOCOL_VERSION: u16 = 14;
+const OP_POLL: u16 = 0x2000;
+const OP_POLL_REPLY: u16 = 0x2100;
+const OP_DMX: u16 = 0x5000;
+const OP_SYNC: u16 = 0x5200;
+
+const POLL_REPLY_SIZE: usize = 239;
+/// Ports described by each ArtPollReply, a node with more universes sends several
+const PORTS_PER_REPLY: usize = 4;
+const SHORT_NAME: &str = "LED Controller";
+const LONG_NAME: &str = "ESP32 LED Controller";
+const PORT_TYPE_OUTPUT: u8 = 0x80; // Outputs DMX512 from the network
+const GOOD_OUTPUT_DATA: u8 = 0x80; // Data is being output
+const STATUS1_NORMAL: u8 = 0xd0; // Indicators normal, addresses set from the front panel
+const STATUS2_15_BIT: u8 = 0x08; // Supports 15 bit port-addresses
+const STYLE_NODE: u8 = 0x00;
+
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ArtNetConfig {
+    pub enabled: bool,
+    /// `universe` is the full 15 bit port-address, net, sub-net and universe together
+    #[serde(flatten)]
+    pub layout: UniverseLayout,
+}
+
+impl ArtNetConfig {
+    pub fn validate(&self) -> Result<()> {
+        self.layout.validate("Art-Net", 0..=MAX_UNIVERSE)
+    }
+}
+
+impl Default for ArtNetConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            layout: UniverseLayout { universe: 0, channel_offset: 0 },
+        }
+    }
+}
+
+
+enum Packet<'a> {
+    Poll,
+    Dmx {
+        universe: u16,
+        /// DMX channel values
+        slots: &'a [u8],
+    },
+    Sync,
+}
+
+/// Anything that isn't needed to drive pixels or be discovered is ignored
+fn parse(data: &[u8]) -> Result<Packet<'_>> {
+    if data.len() < 12 || &data[..8] != ARTNET_ID {
+        return Err(Error::msg("Not an Art-Net packet"))
+    }
+
+    match u16::from_le_bytes([data[8], data[9]]) {
+        OP_POLL => Ok(Packet::Poll),
+        OP_DMX => {
+            if data.len() < 18 {
+                return Err(Error::msg("ArtDmx packet too short"))
+            }
+            let length = u16::from_be_bytes([data[16], data[17]]) as usize;
+            Ok(Packet::Dmx {
+                universe: u16::from_le_bytes([data[14], data[15]]) & MAX_UNIVERSE,
+                slots: &data[18..data.len().min(18 + length)],
+            })
+        },
+        OP_SYNC => Ok(Packet::Sync),
+        opcode => Err(Error::msg(format!("Unsupported opcode {opcode:#06x}"))),
+    }
+}
+
+
+pub(super) fn listen(context: RealtimeContext) -> Result<()> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, PORT))?;
+    socket.set_read_timeout(Some(POLL_INTERVAL))?;
+    log::info!("Listening for Art-Net on port {PORT}");
+
+    let mut receiver = Receiver {
+        context,
+        socket,
+        synced_at: None,
+        last_dmx: None,
+        polls: 0,
+    };
+    let mut buffer = [0; MAX_PACKET_SIZE];
+
+    loop {
+        let (len, sender) = match receiver.socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let config = receiver.context.config();
+        if !config.artnet.enabled {
+            continue
+        }
+
+        match parse(&buffer[..len]) {
+            Ok(packet) => receiver.handle(packet, sender, &config)?,
+            Err(e) => log::debug!("Ignoring Art-Net packet: {e:?}"),
+        }
+    }
+}
+
+
+struct Receiver {
+    context: RealtimeContext,
+    socket: UdpSocket,
+    /// When the last ArtSync arrived, while the controller is syncing its frames
+    synced_at: Option<Instant>,
+    last_dmx: Option<Instant>,
+    /// Replies sent, reported back in the node report
+    polls: u32,
+}
+
+impl Receiver {
+    fn handle(&mut self, packet: Packet, sender: SocketAddr, config: &RealtimeConfig) -> Result<()> {
+        match packet {
+            Packet::Poll => {
+                // Discovery carries on without the strips, a failed reply is only logged
+                if let Err(e) = self.reply(sender, config) {
+                    log::warn!("Unable to reply to ArtPoll from {sender}: {e:?}");
+                }
+                Ok(())
+            },
+            Packet::Dmx { universe, slots } => {
+                let Some((offset, slots)) = config.artnet.layout.placement(universe, slots) else {
+                    return Ok(())
+                };
+                self.last_dmx = Some(Instant::now());
+
+                let synced = self.synced_at.map_or(false, |synced_at| synced_at.elapsed() < SYNC_TIMEOUT);
+                self.context.send(RealtimeUpdate {
+                    source: RealtimeSource::ArtNet,
+                    offset,
+                    colors: rgb_pixels(slots),
+                    show: !synced,
+                    timeout: config.timeout(),
+                })
+            },
+            Packet::Sync => {
+                self.synced_at = Some(Instant::now());
+                self.context.send(RealtimeUpdate {
+                    source: RealtimeSource::ArtNet,
+                    offset: 0,
+                    colors: Vec::new(),
+                    show: true,
+                    timeout: config.timeout(),
+                })
+            },
+        }
+    }
+
+    /// ArtPollReplies for the universes the strips take up, sent back to whoever polled
+    fn reply(&mut self, sender: SocketAddr, config: &RealtimeConfig) -> Result<()> {
+        let ip = match local_ip(sender)? {
+            IpAddr::V4(ip) => ip,
+            IpAddr::V6(_) => return Err(Error::msg("Art-Net only runs over IPv4")),
+        };
+        let led_count = self.context.led_count();
+        let universes: Vec<u16> = config.artnet.layout.universes(led_count)
+            .take_while(|universe| *universe <= MAX_UNIVERSE)
+            .collect();
+        let receiving = self.last_dmx.map_or(false, |last_dmx| last_dmx.elapsed() < config.timeout());
+
+        self.polls = self.polls.wrapping_add(1);
+        let report = format!("#0001 [{:04}] {led_count} LEDs", self.polls % 10000);
+
+        for reply in poll_replies(ip, &universes, receiving, &report) {
+            self.socket.send_to(&reply, (sender.ip(), PORT))?;
+        }
+
+        Ok(())
+    }
+}
+
+
+/// ArtPollReplies describing `universes` as output ports. Ports in one reply share their net
+/// and sub-net, so a new reply starts wherever those change as well as every four ports
+fn poll_replies(ip: Ipv4Addr, universes: &[u16], receiving: bool, report: &str) -> Vec<[u8; POLL_REPLY_SIZE]> {
+    let mut groups: Vec<Vec<u16>> = Vec::new();
+    for &universe in universes {
+        match groups.last_mut() {
+            Some(group) if group.len() < PORTS_PER_REPLY && group[0] >> 4 == universe >> 4 => group.push(universe),
+            _ => groups.push(vec![universe]),
+        }
+    }
+
+    groups.iter().enumerate().map(|(bind_index, group)| {
+        let mut reply = [0; POLL_REPLY_SIZE];
+        reply[..8].copy_from_slice(ARTNET_ID);
+        reply[8..10].copy_from_slice(&OP_POLL_REPLY.to_le_bytes());
+        reply[10..14].copy_from_slice(&ip.octets());
+        reply[14..16].copy_from_slice(&PORT.to_le_bytes());
+        reply[16..18].copy_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+        reply[18] = (group[0] >> 8) as u8;
+        reply[19] = ((group[0] >> 4) & 0x0f) as u8;
+        reply[20..22].copy_from_slice(&[0xff, 0xff]); // No OEM code
+        reply[23] = STATUS1_NORMAL;
+        write_name(&mut reply[26..44], SHORT_NAME);
+        write_name(&mut reply[44..108], LONG_NAME);
+        write_name(&mut reply[108..172], report);
+        reply[173] = group.len() as u8;
+        for (port, universe) in group.iter().enumerate() {
+            reply[174 + port] = PORT_TYPE_OUTPUT;
+            reply[182 + port] = if receiving { GOOD_OUTPUT_DATA } else { 0 };
+            reply[190 + port] = (universe & 0x0f) as u8;
+        }
+        reply[200] = STYLE_NODE;
+        reply[207..211].copy_from_slice(&ip.octets());
+        reply[211] = bind_index as u8 + 1;
+        reply[212] = STATUS2_15_BIT;
+        reply
+    }).collect()
+}
+
+
+/// Null terminated and cut short if it doesn't fit
+fn write_name(field: &mut [u8], name: &str) {
+    let len = name.len().min(field.len() - 1);
+    field[..len].copy_from_slice(&name.as_bytes()[..len]);
+}
+
+/// Address of the interface that traffic to `peer` goes out of
+fn local_ip(peer: SocketAddr) -> Result<IpAddr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.connect(peer)?;
+    Ok(socket.local_addr()?.ip())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dmx_packet(universe: u16, slots: &[u8]) -> Vec<u8> {
+        let mut packet = ARTNET_ID.to_vec();
+        packet.extend(OP_DMX.to_le_bytes());
+        packet.extend(PROTOCOL_VERSION.to_be_bytes());
+        packet.extend([0, 0]); // Sequence and physical
+        packet.extend(universe.to_le_bytes());
+        packet.extend((slots.len() as u16).to_be_bytes());
+        packet.extend(slots);
+        packet
+    }
+
+    #[test]
+    fn parses_packets() -> Result<()> {
+        let packet = dmx_packet(0x8123, &[1, 2, 3]);
+        // The top bit isn't part of the port-address
+        assert!(matches!(parse(&packet)?, Packet::Dmx { universe: 0x0123, slots: [1, 2, 3] }));
+        assert!(parse(&packet[..17]).is_err());
+
+        let mut packet = dmx_packet(0, &[]);
+        packet[8..10].copy_from_slice(&OP_POLL.to_le_bytes());
+        assert!(matches!(parse(&packet)?, Packet::Poll));
+        packet[8..10].copy_from_slice(&OP_SYNC.to_le_bytes());
+        assert!(matches!(parse(&packet)?, Packet::Sync));
+        packet[8..10].copy_from_slice(&OP_POLL_REPLY.to_le_bytes());
+        assert!(parse(&packet).is_err());
+        packet[0] = b'X';
+        assert!(parse(&packet).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn fills_in_poll_replies() {
+        let ip = Ipv4Addr::new(192, 168, 1, 50);
+        let [reply] = poll_replies(ip, &[0x0123, 0x0124], true, "#0001 [0001] 300 LEDs")[..] else {
+            panic!("Expected one reply")
+        };
+
+        assert_eq!(&reply[..8], ARTNET_ID);
+        assert_eq!(u16::from_le_bytes([reply[8], reply[9]]), OP_POLL_REPLY);
+        assert_eq!(reply[10..14], [192, 168, 1, 50]);
+        assert_eq!(u16::from_le_bytes([reply[14], reply[15]]), PORT);
+        assert_eq!(u16::from_be_bytes([reply[16], reply[17]]), PROTOCOL_VERSION);
+        assert_eq!((reply[18], reply[19]), (0x01, 0x02));
+        assert_eq!(&reply[26..41], b"LED Controller\0");
+        assert_eq!(&reply[108..130], b"#0001 [0001] 300 LEDs\0");
+        assert_eq!(reply[173], 2);
+        assert_eq!(reply[174..178], [PORT_TYPE_OUTPUT, PORT_TYPE_OUTPUT, 0, 0]);
+        assert_eq!(reply[182..186], [GOOD_OUTPUT_DATA, GOOD_OUTPUT_DATA, 0, 0]);
+        assert_eq!(reply[190..194], [0x03, 0x04, 0, 0]);
+        assert_eq!(reply[207..211], [192, 168, 1, 50]);
+        assert_eq!(reply[211], 1);
+        assert_eq!(reply[212], STATUS2_15_BIT);
+    }
+
+    #[test]
+    fn groups_ports_by_sub_net() {
+        // Sub-net 0 ends after 0x0f, and net 0 after 0xff
+        let universes = [0x0e, 0x0f, 0x10, 0x11, 0x12, 0x13, 0x14, 0xff, 0x100];
+        let replies = poll_replies(Ipv4Addr::LOCALHOST, &universes, false, "");
+
+        let described: Vec<_> = replies.iter()
+            .map(|reply| (reply[18], reply[19], reply[190..190 + reply[173] as usize].to_vec(), reply[211]))
+            .collect();
+        assert_eq!(described, [
+            (0, 0, vec![0x0e, 0x0f], 1),
+            (0, 1, vec![0x00, 0x01, 0x02, 0x03], 2),
+            (0, 1, vec![0x04], 3),
+            (0, 0x0f, vec![0x0f], 4),
+            (1, 0, vec![0x00], 5),
+        ]);
+        assert!(replies.iter().all(|reply| reply[182] == 0));
+    }
+}
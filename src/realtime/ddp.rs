@@ -0,0 +1,191 @@
+use anyhow::{Result, Error};
+use serde::{Serialize, Deserialize};
+
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, UdpSocket};
+
+use super::{rgb_pixels, RealtimeConfig, RealtimeContext, POLL_INTERVAL};
+use crate::led_control::{Color, RealtimeSource, RealtimeUpdate};
+
+
+pub const PORT: u16 = 4048;
+
+const MAX_PACKET_SIZE: usize = 1500;
+const HEADER_SIZE: usize = 10;
+const TIMECODE_SIZE: usize = 4;
+const VERSION_MASK: u8 = 0xc0;
+const VERSION_1: u8 = 0x40;
+const FLAG_TIMECODE: u8 = 0x10;
+const FLAG_STORAGE: u8 = 0x08;
+const FLAG_REPLY: u8 = 0x04;
+const FLAG_QUERY: u8 = 0x02;
+const FLAG_PUSH: u8 = 0x01;
+const TYPE_RGBW: u8 = 0x1b;
+const ID_DISPLAY: u8 = 1;
+const ID_ALL: u8 = 255;
+
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DdpConfig {
+    pub enabled: bool,
+}
+
+impl Default for DdpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+        }
+    }
+}
+
+
+struct Packet<'a> {
+    /// Byte offset into the whole frame
+    offset: u32,
+    /// Bytes per pixel, 4 for RGBW and 3 for everything else
+    channels: usize,
+    push: bool,
+    data: &'a [u8],
+}
+
+/// Only pixel data for the display is taken, queries and config are left for other devices
+fn parse(data: &[u8]) -> Result<Packet<'_>> {
+    if data.len() < HEADER_SIZE || data[0] & VERSION_MASK != VERSION_1 {
+        return Err(Error::msg("Not a DDP v1 packet"))
+    }
+    let flags = data[0];
+    if flags & (FLAG_QUERY | FLAG_REPLY | FLAG_STORAGE) != 0 {
+        return Err(Error::msg(format!("Unsupported flags {flags:#04x}")))
+    }
+    if !matches!(data[3], ID_DISPLAY | ID_ALL) {
+        return Err(Error::msg(format!("Unsupported destination {}", data[3])))
+    }
+
+    let start = if flags & FLAG_TIMECODE != 0 { HEADER_SIZE + TIMECODE_SIZE } else { HEADER_SIZE };
+    let length = u16::from_be_bytes([data[8], data[9]]) as usize;
+    Ok(Packet {
+        offset: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+        channels: if data[2] == TYPE_RGBW { 4 } else { 3 },
+        push: flags & FLAG_PUSH != 0,
+        data: data.get(start..data.len().min(start + length)).unwrap_or_default(),
+    })
+}
+
+
+pub(super) fn listen(context: RealtimeContext) -> Result<()> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, PORT))?;
+    socket.set_read_timeout(Some(POLL_INTERVAL))?;
+    log::info!("Listening for DDP on port {PORT}");
+
+    let mut buffer = [0; MAX_PACKET_SIZE];
+
+    loop {
+        let len = match socket.recv(&mut buffer) {
+            Ok(len) => len,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let config = context.config();
+        if !config.ddp.enabled {
+            continue
+        }
+
+        match parse(&buffer[..len]) {
+            Ok(packet) => handle(&context, packet, &config)?,
+            Err(e) => log::debug!("Ignoring DDP packet: {e:?}"),
+        }
+    }
+}
+
+
+/// Packets for long strips carry part of the frame each, placed by their offset and shown
+/// once one arrives with the push flag
+fn handle(context: &RealtimeContext, packet: Packet, config: &RealtimeConfig) -> Result<()> {
+    // An offset partway into a pixel drops the rest of that pixel
+    let channels = packet.channels as u32;
+    let skip = (channels - packet.offset % channels) % channels;
+    // Offsets near the top of the 32 bit range are far past the end of any strip
+    let Some(start) = packet.offset.checked_add(skip) else {
+        log::debug!("Ignoring DDP packet at offset {}", packet.offset);
+        return Ok(())
+    };
+    let data = packet.data.get(skip as usize..).unwrap_or_default();
+    let colors = match packet.channels {
+        4 => data.chunks_exact(4).map(|rgbw| Color::rgbw(rgbw[0], rgbw[1], rgbw[2], rgbw[3])).collect(),
+        _ => rgb_pixels(data),
+    };
+
+    context.send(RealtimeUpdate {
+        source: RealtimeSource::Ddp,
+        offset: (start / channels) as usize,
+        colors,
+        show: packet.push,
+        timeout: config.timeout(),
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::realtime::{sent_pixels, test_context};
+
+    const TYPE_RGB: u8 = 0x0b;
+
+    fn packet(flags: u8, data_type: u8, offset: u32, payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![flags, 0, data_type, ID_DISPLAY];
+        packet.extend(offset.to_be_bytes());
+        packet.extend((payload.len() as u16).to_be_bytes());
+        packet.extend(payload);
+        packet
+    }
+
+    #[test]
+    fn parses_the_header() -> Result<()> {
+        let data = packet(VERSION_1 | FLAG_PUSH, TYPE_RGB, 30, &[1, 2, 3, 4, 5, 6]);
+        let parsed = parse(&data)?;
+        assert_eq!(parsed.offset, 30);
+        assert_eq!(parsed.channels, 3);
+        assert!(parsed.push);
+        assert_eq!(parsed.data, [1, 2, 3, 4, 5, 6]);
+
+        assert_eq!(parse(&packet(VERSION_1, TYPE_RGBW, 0, &[]))?.channels, 4);
+        assert!(parse(&data[..HEADER_SIZE - 1]).is_err());
+        assert!(parse(&packet(0x80, TYPE_RGB, 0, &[])).is_err());
+        assert!(parse(&packet(VERSION_1 | FLAG_QUERY, TYPE_RGB, 0, &[])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn skips_the_timecode() -> Result<()> {
+        let mut data = packet(VERSION_1 | FLAG_TIMECODE, TYPE_RGB, 0, &[]);
+        data.extend([9, 9, 9, 9, 1, 2, 3]);
+        data[9] = 3;
+        let parsed = parse(&data)?;
+        assert!(!parsed.push);
+        assert_eq!(parsed.data, [1, 2, 3]);
+        Ok(())
+    }
+
+    #[test]
+    fn drops_partial_pixels() -> Result<()> {
+        let (context, controller_rx) = test_context(&[10]);
+        // Starts on the second byte of the second pixel and ends partway into the fourth
+        let data = packet(VERSION_1 | FLAG_PUSH, TYPE_RGB, 4, &[7, 8, 1, 2, 3, 4, 5, 6, 9]);
+        handle(&context, parse(&data)?, &RealtimeConfig::default())?;
+
+        assert_eq!(sent_pixels(&controller_rx), [(2, vec![[1, 2, 3, 0], [4, 5, 6, 0]], true)]);
+        Ok(())
+    }
+
+    #[test]
+    fn drops_offsets_that_overflow() -> Result<()> {
+        let (context, controller_rx) = test_context(&[10]);
+        let data = packet(VERSION_1 | FLAG_PUSH, TYPE_RGBW, u32::MAX, &[1, 2, 3, 4, 5]);
+        handle(&context, parse(&data)?, &RealtimeConfig::default())?;
+
+        assert!(sent_pixels(&controller_rx).is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,325 @@
+use anyhow::{Result, Error};
+use serde::{Serialize, Deserialize};
+
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, UdpSocket};
+use std::time::Instant;
+
+use super::{rgb_pixels, RealtimeConfig, RealtimeContext, UniverseLayout, POLL_INTERVAL};
+use crate::led_control::{RealtimeSource, RealtimeUpdate};
+
+
+pub const PORT: u16 = 5568;
+const MAX_UNIVERSE: u16 = 63999;
+/// lwIP only has room for a handful of multicast groups
+const MAX_MULTICAST_UNIVERSES: usize = 7;
+
+const MAX_PACKET_SIZE: usize = 638;
+const ACN_PACKET_ID: &[u8] = b"ASC-E1.17\0\0\0";
+const VECTOR_ROOT_DATA: u32 = 0x04;
+const VECTOR_ROOT_EXTENDED: u32 = 0x08;
+const VECTOR_FRAME_DATA: u32 = 0x02;
+const VECTOR_FRAME_SYNC: u32 = 0x01;
+const OPTION_PREVIEW: u8 = 0x80;
+const OPTION_TERMINATED: u8 = 0x40;
+const DMX_START_CODE: u8 = 0x00;
+
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct E131Config {
+    pub enabled: bool,
+    #[serde(flatten)]
+    pub layout: UniverseLayout,
+    /// Joins the multicast group of each universe as well as taking unicast packets
+    pub multicast: bool,
+}
+
+impl E131Config {
+    pub fn validate(&self) -> Result<()> {
+        self.layout.validate("E1.31", 1..=MAX_UNIVERSE)
+    }
+}
+
+impl Default for E131Config {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            layout: UniverseLayout { universe: 1, channel_offset: 0 },
+            multicast: true,
+        }
+    }
+}
+
+
+enum Packet<'a> {
+    Data {
+        universe: u16,
+        /// Non-zero if the sender holds frames back until a sync packet on this address
+        sync_address: u16,
+        options: u8,
+        /// DMX channel values after the start code
+        slots: &'a [u8],
+    },
+    Sync,
+}
+
+/// Only the fields that matter for driving pixels are checked, the rest is taken on trust
+fn parse(data: &[u8]) -> Result<Packet<'_>> {
+    let u16_at = |i: usize| u16::from_be_bytes([data[i], data[i + 1]]);
+    let u32_at = |i: usize| u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+
+    if data.len() < 49 || &data[4..16] != ACN_PACKET_ID {
+        return Err(Error::msg("Not an ACN packet"))
+    }
+
+    match (u32_at(18), u32_at(40)) {
+        (VECTOR_ROOT_DATA, VECTOR_FRAME_DATA) => {
+            if data.len() < 126 {
+                return Err(Error::msg("Data packet too short"))
+            }
+            if data[125] != DMX_START_CODE {
+                return Err(Error::msg(format!("Unsupported start code {}", data[125])))
+            }
+            // Property value count includes the start code
+            let count = (u16_at(123) as usize).saturating_sub(1);
+            Ok(Packet::Data {
+                universe: u16_at(113),
+                sync_address: u16_at(109),
+                options: data[112],
+                slots: &data[126..data.len().min(126 + count)],
+            })
+        },
+        (VECTOR_ROOT_EXTENDED, VECTOR_FRAME_SYNC) => Ok(Packet::Sync),
+        (root, frame) => Err(Error::msg(format!("Unsupported vectors {root:#x}/{frame:#x}"))),
+    }
+}
+
+
+pub(super) fn listen(context: RealtimeContext) -> Result<()> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, PORT))?;
+    socket.set_read_timeout(Some(POLL_INTERVAL))?;
+    log::info!("Listening for E1.31 on port {PORT}");
+
+    let mut receiver = Receiver {
+        context,
+        socket,
+        joined: Vec::new(),
+        synced_at: None,
+    };
+    let mut buffer = [0; MAX_PACKET_SIZE];
+
+    loop {
+        let config = receiver.context.config();
+        receiver.join_universes(&config.e131);
+
+        let len = match receiver.socket.recv(&mut buffer) {
+            Ok(len) => len,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        if !config.e131.enabled {
+            continue
+        }
+
+        match parse(&buffer[..len]) {
+            Ok(packet) => receiver.handle(packet, &config)?,
+            Err(e) => log::debug!("Ignoring E1.31 packet: {e:?}"),
+        }
+    }
+}
+
+
+struct Receiver {
+    context: RealtimeContext,
+    socket: UdpSocket,
+    /// Universes whose multicast groups have been joined
+    joined: Vec<u16>,
+    /// When the last sync packet arrived, while the sender is syncing its frames
+    synced_at: Option<Instant>,
+}
+
+impl Receiver {
+    fn handle(&mut self, packet: Packet, config: &RealtimeConfig) -> Result<()> {
+        match packet {
+            Packet::Data { universe, sync_address, options, slots } => {
+                if options & OPTION_PREVIEW != 0 {
+                    return Ok(())
+                }
+                if options & OPTION_TERMINATED != 0 {
+                    return self.context.end(RealtimeSource::E131)
+                }
+                let Some((offset, slots)) = config.e131.layout.placement(universe, slots) else {
+                    return Ok(())
+                };
+
+                // Frames are shown as each universe arrives, unless the sender is syncing
+                // them and hasn't gone quiet
+                let synced = sync_address != 0
+                    && self.synced_at.map_or(false, |synced_at| synced_at.elapsed() < config.timeout());
+                self.context.send(RealtimeUpdate {
+                    source: RealtimeSource::E131,
+                    offset,
+                    colors: rgb_pixels(slots),
+                    show: !synced,
+                    timeout: config.timeout(),
+                })
+            },
+            Packet::Sync => {
+                self.synced_at = Some(Instant::now());
+                self.context.send(RealtimeUpdate {
+                    source: RealtimeSource::E131,
+                    offset: 0,
+                    colors: Vec::new(),
+                    show: true,
+                    timeout: config.timeout(),
+                })
+            },
+        }
+    }
+
+    /// Keeps the joined groups in line with the config and strip length. Joining fails until
+    /// the network is up, so anything that didn't work is tried again on the next call
+    fn join_universes(&mut self, config: &E131Config) {
+        let wanted: Vec<u16> = if config.enabled && config.multicast {
+            config.layout.universes(self.context.led_count()).take(MAX_MULTICAST_UNIVERSES).collect()
+        } else {
+            Vec::new()
+        };
+        if wanted == self.joined {
+            return
+        }
+
+        for &universe in self.joined.iter().filter(|universe| !wanted.contains(universe)) {
+            if let Err(e) = self.socket.leave_multicast_v4(&group(universe), &Ipv4Addr::UNSPECIFIED) {
+                log::warn!("Unable to leave E1.31 universe {universe}: {e:?}");
+            }
+        }
+        self.joined.retain(|universe| wanted.contains(universe));
+
+        for &universe in &wanted {
+            if self.joined.contains(&universe) {
+                continue
+            }
+            match self.socket.join_multicast_v4(&group(universe), &Ipv4Addr::UNSPECIFIED) {
+                Ok(_) => {
+                    log::info!("Joined E1.31 universe {universe}");
+                    self.joined.push(universe);
+                },
+                Err(e) => log::debug!("Unable to join E1.31 universe {universe}: {e:?}"),
+            }
+        }
+        self.joined.sort_unstable();
+    }
+}
+
+
+/// Multicast address sACN uses for a universe
+fn group(universe: u16) -> Ipv4Addr {
+    let [high, low] = universe.to_be_bytes();
+    Ipv4Addr::new(239, 255, high, low)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::led_control::ControllerCommand;
+    use crate::realtime::{sent_pixels, test_context};
+
+    use std::sync::mpsc;
+
+    fn data_packet(universe: u16, sync_address: u16, options: u8, slots: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0; 126];
+        packet[4..16].copy_from_slice(ACN_PACKET_ID);
+        packet[18..22].copy_from_slice(&VECTOR_ROOT_DATA.to_be_bytes());
+        packet[40..44].copy_from_slice(&VECTOR_FRAME_DATA.to_be_bytes());
+        packet[109..111].copy_from_slice(&sync_address.to_be_bytes());
+        packet[112] = options;
+        packet[113..115].copy_from_slice(&universe.to_be_bytes());
+        packet[123..125].copy_from_slice(&(slots.len() as u16 + 1).to_be_bytes());
+        packet[125] = DMX_START_CODE;
+        packet.extend(slots);
+        packet
+    }
+
+    fn sync_packet() -> Vec<u8> {
+        let mut packet = vec![0; 49];
+        packet[4..16].copy_from_slice(ACN_PACKET_ID);
+        packet[18..22].copy_from_slice(&VECTOR_ROOT_EXTENDED.to_be_bytes());
+        packet[40..44].copy_from_slice(&VECTOR_FRAME_SYNC.to_be_bytes());
+        packet
+    }
+
+    fn receiver() -> Result<(Receiver, mpsc::Receiver<ControllerCommand>)> {
+        let (context, controller_rx) = test_context(&[10]);
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+        Ok((Receiver { context, socket, joined: Vec::new(), synced_at: None }, controller_rx))
+    }
+
+    #[test]
+    fn parses_data_packets() -> Result<()> {
+        let packet = data_packet(7, 9, OPTION_PREVIEW, &[1, 2, 3]);
+        let Packet::Data { universe, sync_address, options, slots } = parse(&packet)? else {
+            panic!("Not a data packet")
+        };
+        assert_eq!((universe, sync_address, options, slots), (7, 9, OPTION_PREVIEW, &[1, 2, 3][..]));
+
+        // Slots past the end of the packet are left out
+        let mut packet = data_packet(1, 0, 0, &[1, 2, 3]);
+        packet[123..125].copy_from_slice(&100u16.to_be_bytes());
+        assert!(matches!(parse(&packet)?, Packet::Data { slots: [1, 2, 3], .. }));
+
+        assert!(parse(&data_packet(1, 0, 0, &[])[..125]).is_err());
+        let mut packet = data_packet(1, 0, 0, &[1, 2, 3]);
+        packet[125] = 0xdd;
+        assert!(parse(&packet).is_err());
+        packet[4] = b'X';
+        assert!(parse(&packet).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parses_sync_packets() -> Result<()> {
+        assert!(matches!(parse(&sync_packet())?, Packet::Sync));
+        assert!(parse(&sync_packet()[..48]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn skips_preview_and_ends_on_termination() -> Result<()> {
+        let (mut receiver, controller_rx) = receiver()?;
+        let config = RealtimeConfig::default();
+
+        receiver.handle(parse(&data_packet(1, 0, OPTION_PREVIEW, &[1, 2, 3]))?, &config)?;
+        assert!(controller_rx.try_recv().is_err());
+
+        receiver.handle(parse(&data_packet(1, 0, OPTION_TERMINATED, &[1, 2, 3]))?, &config)?;
+        assert!(matches!(controller_rx.try_recv(), Ok(ControllerCommand::EndRealtime(RealtimeSource::E131))));
+        Ok(())
+    }
+
+    #[test]
+    fn holds_frames_for_sync() -> Result<()> {
+        let (mut receiver, controller_rx) = receiver()?;
+        let config = RealtimeConfig::default();
+
+        // Shown straight away until the sender starts syncing
+        receiver.handle(parse(&data_packet(1, 5, 0, &[1, 2, 3]))?, &config)?;
+        assert_eq!(sent_pixels(&controller_rx), [(0, vec![[1, 2, 3, 0]], true)]);
+
+        receiver.handle(parse(&sync_packet())?, &config)?;
+        receiver.handle(parse(&data_packet(1, 5, 0, &[4, 5, 6]))?, &config)?;
+        receiver.handle(parse(&sync_packet())?, &config)?;
+        assert_eq!(sent_pixels(&controller_rx), [
+            (0, vec![], true),
+            (0, vec![[4, 5, 6, 0]], false),
+            (0, vec![], true),
+        ]);
+
+        // Senders that don't sync a universe have it shown as it arrives
+        receiver.handle(parse(&data_packet(1, 0, 0, &[7, 8, 9]))?, &config)?;
+        assert_eq!(sent_pixels(&controller_rx), [(0, vec![[7, 8, 9, 0]], true)]);
+        Ok(())
+    }
+}
@@ -0,0 +1,190 @@
+use anyhow::{Result, Error};
+use serde::{Serialize, Deserialize};
+
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::Duration;
+
+use crate::led_control::{Color, ControllerCommand, LEDControllerService, OutputConfig, RealtimeSource, RealtimeUpdate, StripConfig};
+
+mod artnet;
+mod ddp;
+mod e131;
+mod opc;
+mod universe;
+mod wled;
+
+pub use artnet::ArtNetConfig;
+pub use ddp::DdpConfig;
+pub use e131::E131Config;
+pub use opc::OpcConfig;
+pub use universe::UniverseLayout;
+pub use wled::{WledConfig, PORT as WLED_PORT};
+
+
+const CONFIG_MUTEX_ERR: &str = "Failed to unlock realtime config mutex";
+const OUTPUT_MUTEX_ERR: &str = "Failed to unlock current output config mutex";
+
+/// How often listeners wake up without any packets to pick up config changes
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+
+/// Network protocols that can take over the strips from the effects
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RealtimeConfig {
+    /// How long a stream can go quiet before the effects come back
+    pub timeout_ms: u64,
+    pub e131: E131Config,
+    pub artnet: ArtNetConfig,
+    pub ddp: DdpConfig,
+    /// WLED's UDP realtime protocols, which set their own timeout
+    pub wled: WledConfig,
+    pub opc: OpcConfig,
+}
+
+impl RealtimeConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.timeout_ms == 0 {
+            return Err(Error::msg("Realtime timeout can't be 0"))
+        }
+        self.e131.validate()?;
+        self.artnet.validate()
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+}
+
+impl Default for RealtimeConfig {
+    fn default() -> Self {
+        Self {
+            // Data loss timeout from the E1.31 spec
+            timeout_ms: 2500,
+            e131: E131Config::default(),
+            artnet: ArtNetConfig::default(),
+            ddp: DdpConfig::default(),
+            wled: WledConfig::default(),
+            opc: OpcConfig::default(),
+        }
+    }
+}
+
+
+/// Listens for realtime protocols and forwards their pixels to the controller
+pub struct RealtimeService {
+    _handles: Vec<thread::JoinHandle<()>>,
+    #[cfg_attr(not(target_os = "espidf"), allow(dead_code))]
+    cur_config: Arc<Mutex<RealtimeConfig>>,
+}
+
+impl RealtimeService {
+    pub fn init(config: RealtimeConfig, led_ctrl_svc: &LEDControllerService) -> Result<Self> {
+        let cur_config = Arc::new(Mutex::new(config));
+        let context = RealtimeContext {
+            config: cur_config.clone(),
+            output: led_ctrl_svc.current_output().clone(),
+            controller_tx: led_ctrl_svc.controller_tx.clone(),
+        };
+
+        Ok(Self {
+            _handles: vec![
+                spawn("E1.31", context.clone(), e131::listen)?,
+                spawn("Art-Net", context.clone(), artnet::listen)?,
+                spawn("DDP", context.clone(), ddp::listen)?,
+                spawn("WLED realtime", context.clone(), wled::listen)?,
+                spawn("OPC", context, opc::listen)?,
+            ],
+            cur_config,
+        })
+    }
+
+    /// Listeners pick up changes made through this with the next packet
+    #[cfg_attr(not(target_os = "espidf"), allow(dead_code))]
+    pub fn current_config(&self) -> &Arc<Mutex<RealtimeConfig>> {
+        &self.cur_config
+    }
+}
+
+
+fn spawn(protocol: &'static str, context: RealtimeContext, listen: fn(RealtimeContext) -> Result<()>) -> Result<thread::JoinHandle<()>> {
+    Ok(thread::Builder::new()
+        .stack_size(6144)
+        .spawn(move || {
+            if let Err(e) = listen(context) {
+                log::error!("Error running {protocol} receiver: {e:?}");
+            }
+        })?)
+}
+
+
+/// Colors from packed RGB triplets, ignoring any trailing partial pixel
+fn rgb_pixels(data: &[u8]) -> Vec<Color> {
+    data.chunks_exact(3).map(|rgb| Color::rgb(rgb[0], rgb[1], rgb[2])).collect()
+}
+
+
+/// What each listener needs to map packets onto the strips
+#[derive(Clone)]
+struct RealtimeContext {
+    config: Arc<Mutex<RealtimeConfig>>,
+    output: Arc<Mutex<OutputConfig>>,
+    controller_tx: mpsc::Sender<ControllerCommand>,
+}
+
+impl RealtimeContext {
+    fn config(&self) -> RealtimeConfig {
+        self.config.lock().expect(CONFIG_MUTEX_ERR).clone()
+    }
+
+    fn led_count(&self) -> usize {
+        self.output.lock().expect(OUTPUT_MUTEX_ERR).led_count()
+    }
+
+    fn strips(&self) -> Vec<StripConfig> {
+        self.output.lock().expect(OUTPUT_MUTEX_ERR).strips.clone()
+    }
+
+    /// Fails once the controller has gone away
+    fn send(&self, update: RealtimeUpdate) -> Result<()> {
+        self.controller_tx.send(ControllerCommand::Realtime(update))?;
+        Ok(())
+    }
+
+    fn end(&self, source: RealtimeSource) -> Result<()> {
+        self.controller_tx.send(ControllerCommand::EndRealtime(source))?;
+        Ok(())
+    }
+}
+
+
+/// Stands in for the controller in the protocol tests, with strips of the given lengths
+#[cfg(test)]
+fn test_context(led_counts: &[usize]) -> (RealtimeContext, mpsc::Receiver<ControllerCommand>) {
+    let strips = led_counts.iter().enumerate()
+        .map(|(i, &led_count)| StripConfig { pin: 16 + i as u8, led_count, ..Default::default() })
+        .collect();
+    let (controller_tx, controller_rx) = mpsc::channel();
+    let context = RealtimeContext {
+        config: Arc::new(Mutex::new(RealtimeConfig::default())),
+        output: Arc::new(Mutex::new(OutputConfig { strips, ..Default::default() })),
+        controller_tx,
+    };
+    (context, controller_rx)
+}
+
+/// Pixels sent to the controller so far, as the offset, RGBW colors and whether to show them
+#[cfg(test)]
+fn sent_pixels(controller_rx: &mpsc::Receiver<ControllerCommand>) -> Vec<(usize, Vec<[u8; 4]>, bool)> {
+    controller_rx.try_iter()
+        .filter_map(|command| match command {
+            ControllerCommand::Realtime(update) => Some((
+                update.offset,
+                update.colors.iter().map(|color| [color.r, color.g, color.b, color.w]).collect(),
+                update.show,
+            )),
+            _ => None,
+        })
+        .collect()
+}
@@ -0,0 +1,231 @@
+use anyhow::{Result, Error};
+use serde::{Serialize, Deserialize};
+
+use std::io::{self, ErrorKind, Read};
+use std::net::{Ipv4Addr, TcpListener};
+use std::time::Duration;
+
+use super::{rgb_pixels, RealtimeConfig, RealtimeContext};
+use crate::led_control::{RealtimeSource, RealtimeUpdate};
+
+
+pub const PORT: u16 = 7890;
+
+const HEADER_SIZE: usize = 4;
+/// Longest system exclusive message understood, kept even with fewer LEDs than that
+const MAX_SYSEX_SIZE: usize = 5;
+/// A client quiet for this long is dropped so the next one waiting can connect
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Sent to every output, any other channel goes to the strip of the same number
+const CHANNEL_BROADCAST: u8 = 0;
+const COMMAND_SET_PIXELS: u8 = 0;
+const COMMAND_SYSEX: u8 = 255;
+const SYSTEM_FADECANDY: u16 = 0x0001;
+const FADECANDY_FIRMWARE_CONFIG: u16 = 0x0002;
+const FIRMWARE_NO_DITHERING: u8 = 0x01;
+const FIRMWARE_NO_INTERPOLATION: u8 = 0x02;
+const FIRMWARE_MANUAL_LED: u8 = 0x04;
+const FIRMWARE_LED_ON: u8 = 0x08;
+
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OpcConfig {
+    pub enabled: bool,
+}
+
+impl Default for OpcConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+        }
+    }
+}
+
+
+pub(super) fn listen(context: RealtimeContext) -> Result<()> {
+    let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, PORT))?;
+    log::info!("Listening for OPC on port {PORT}");
+
+    // One client at a time, others wait in the backlog until it disconnects
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::warn!("Unable to accept OPC client: {e:?}");
+                continue
+            }
+        };
+        let peer = stream.peer_addr().map(|peer| peer.to_string()).unwrap_or_default();
+        log::info!("OPC client {peer} connected");
+
+        let served = stream.set_read_timeout(Some(CLIENT_TIMEOUT))
+            .map_err(Error::from)
+            .and_then(|_| serve(&context, stream));
+        match served {
+            Ok(_) => log::info!("OPC client {peer} disconnected"),
+            Err(e) => log::warn!("OPC client {peer} dropped: {e:?}"),
+        }
+        // Nothing more is coming, so there's no point waiting out the timeout
+        context.end(RealtimeSource::Opc)?;
+    }
+
+    Ok(())
+}
+
+
+/// Handles messages until the client hangs up
+fn serve(context: &RealtimeContext, mut stream: impl Read) -> Result<()> {
+    let mut header = [0; HEADER_SIZE];
+    let mut data = Vec::new();
+
+    loop {
+        match stream.read_exact(&mut header) {
+            Ok(_) => (),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+        let [channel, command, high, low] = header;
+        let len = u16::from_be_bytes([high, low]) as usize;
+
+        // Anything past the last LED is read off the connection without being kept
+        let keep = len.min((context.led_count() * 3).max(MAX_SYSEX_SIZE));
+        data.resize(keep, 0);
+        stream.read_exact(&mut data)?;
+        let skip = (len - keep) as u64;
+        if io::copy(&mut (&mut stream).take(skip), &mut io::sink())? < skip {
+            return Err(Error::msg("Connection closed partway through a message"))
+        }
+
+        let config = context.config();
+        if !config.opc.enabled {
+            continue
+        }
+
+        match command {
+            COMMAND_SET_PIXELS => set_pixels(context, channel, &data, &config)?,
+            COMMAND_SYSEX => {
+                if let Err(e) = sysex(&data) {
+                    log::debug!("Ignoring OPC system exclusive message: {e:?}");
+                }
+            },
+            command => log::debug!("Ignoring OPC command {command}"),
+        }
+    }
+}
+
+
+fn set_pixels(context: &RealtimeContext, channel: u8, data: &[u8], config: &RealtimeConfig) -> Result<()> {
+    let mut colors = rgb_pixels(data);
+
+    let offset = if channel == CHANNEL_BROADCAST {
+        0
+    } else {
+        let strips = context.strips();
+        let Some(strip) = strips.get(channel as usize - 1) else {
+            log::debug!("Ignoring OPC channel {channel}, there are only {} strips", strips.len());
+            return Ok(())
+        };
+        colors.truncate(strip.led_count);
+        strips[..channel as usize - 1].iter().map(|strip| strip.led_count).sum()
+    };
+
+    context.send(RealtimeUpdate {
+        source: RealtimeSource::Opc,
+        offset,
+        colors,
+        show: true,
+        timeout: config.timeout(),
+    })
+}
+
+
+fn sysex(data: &[u8]) -> Result<()> {
+    if data.len() < 4 {
+        return Err(Error::msg("Message too short"))
+    }
+    let system = u16::from_be_bytes([data[0], data[1]]);
+    let command = u16::from_be_bytes([data[2], data[3]]);
+
+    match (system, command) {
+        (SYSTEM_FADECANDY, FADECANDY_FIRMWARE_CONFIG) => {
+            // Frames are already shown as they arrive, without dithering or interpolation, and
+            // there is no status LED, so the options are only logged
+            let bits = data.get(4).copied().ok_or_else(|| Error::msg("Firmware config has no options"))?;
+            log::info!(
+                "OPC firmware config: dithering {}, interpolation {}, status LED {}",
+                bits & FIRMWARE_NO_DITHERING == 0,
+                bits & FIRMWARE_NO_INTERPOLATION == 0,
+                match (bits & FIRMWARE_MANUAL_LED != 0, bits & FIRMWARE_LED_ON != 0) {
+                    (false, _) => "auto",
+                    (true, true) => "on",
+                    (true, false) => "off",
+                },
+            );
+            Ok(())
+        },
+        _ => Err(Error::msg(format!("Unsupported system {system:#06x} command {command:#06x}"))),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::realtime::{sent_pixels, test_context};
+
+    fn message(channel: u8, command: u8, data: &[u8]) -> Vec<u8> {
+        let mut message = vec![channel, command];
+        message.extend((data.len() as u16).to_be_bytes());
+        message.extend(data);
+        message
+    }
+
+    #[test]
+    fn places_pixels_by_channel() -> Result<()> {
+        let (context, controller_rx) = test_context(&[2, 3]);
+        let stream = [
+            message(CHANNEL_BROADCAST, COMMAND_SET_PIXELS, &[1, 1, 1, 2, 2, 2]),
+            // Cut to the length of the strip
+            message(1, COMMAND_SET_PIXELS, &[3, 3, 3, 4, 4, 4, 5, 5, 5]),
+            message(2, COMMAND_SET_PIXELS, &[6, 6, 6]),
+            // Only two strips
+            message(3, COMMAND_SET_PIXELS, &[7, 7, 7]),
+        ].concat();
+        serve(&context, stream.as_slice())?;
+
+        assert_eq!(sent_pixels(&controller_rx), [
+            (0, vec![[1, 1, 1, 0], [2, 2, 2, 0]], true),
+            (0, vec![[3, 3, 3, 0], [4, 4, 4, 0]], true),
+            (2, vec![[6, 6, 6, 0]], true),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn drops_pixels_past_the_last_led() -> Result<()> {
+        let (context, controller_rx) = test_context(&[2]);
+        let pixels: Vec<u8> = (1..=12).collect();
+        let stream = [
+            message(CHANNEL_BROADCAST, COMMAND_SET_PIXELS, &pixels),
+            // Still read from the start of the next message
+            message(CHANNEL_BROADCAST, COMMAND_SET_PIXELS, &[9, 9, 9]),
+        ].concat();
+        serve(&context, stream.as_slice())?;
+
+        assert_eq!(sent_pixels(&controller_rx), [
+            (0, vec![[1, 2, 3, 0], [4, 5, 6, 0]], true),
+            (0, vec![[9, 9, 9, 0]], true),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn fails_on_cut_off_messages() {
+        let (context, _controller_rx) = test_context(&[2]);
+        let stream = message(CHANNEL_BROADCAST, COMMAND_SET_PIXELS, &[0; 12]);
+        // In the part kept and in the part dropped
+        assert!(serve(&context, &stream[..7]).is_err());
+        assert!(serve(&context, &stream[..13]).is_err());
+    }
+}
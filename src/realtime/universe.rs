@@ -0,0 +1,55 @@
+use anyhow::{Result, Error};
+use serde::{Serialize, Deserialize};
+
+use std::ops::{Range, RangeInclusive};
+
+
+/// RGB pixels that fit in the 512 channels of a DMX universe
+pub const PIXELS_PER_UNIVERSE: usize = 170;
+const CHANNELS_PER_UNIVERSE: usize = PIXELS_PER_UNIVERSE * 3;
+
+
+/// Where DMX universes land on the strips. Universes follow on from `universe`, each carrying
+/// 170 pixels, and the first can start partway in at `channel_offset`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UniverseLayout {
+    pub universe: u16,
+    /// Channels to skip at the start of the first universe
+    pub channel_offset: usize,
+}
+
+impl UniverseLayout {
+    /// `valid` is the range of universes the protocol can address
+    pub fn validate(&self, protocol: &str, valid: RangeInclusive<u16>) -> Result<()> {
+        if !valid.contains(&self.universe) {
+            return Err(Error::msg(format!("{protocol} universe must be between {} and {}", valid.start(), valid.end())))
+        }
+        if self.channel_offset >= CHANNELS_PER_UNIVERSE - 2 {
+            return Err(Error::msg(format!("{protocol} channel offset must leave room for a pixel, below {}", CHANNELS_PER_UNIVERSE - 2)))
+        }
+        Ok(())
+    }
+
+    /// Universes needed to cover the strips
+    pub fn universes(&self, led_count: usize) -> Range<u16> {
+        let rest = led_count.saturating_sub(self.first_universe_pixels());
+        let count = 1 + (rest + PIXELS_PER_UNIVERSE - 1) / PIXELS_PER_UNIVERSE;
+        self.universe..self.universe.saturating_add(count as u16)
+    }
+
+    /// First pixel of a universe and the channels in it that carry pixels, or None for a
+    /// universe before the start
+    pub fn placement<'a>(&self, universe: u16, slots: &'a [u8]) -> Option<(usize, &'a [u8])> {
+        let index = universe.checked_sub(self.universe)? as usize;
+        let slots = &slots[..slots.len().min(CHANNELS_PER_UNIVERSE)];
+        if index == 0 {
+            Some((0, slots.get(self.channel_offset..).unwrap_or_default()))
+        } else {
+            Some((self.first_universe_pixels() + (index - 1) * PIXELS_PER_UNIVERSE, slots))
+        }
+    }
+
+    fn first_universe_pixels(&self) -> usize {
+        (CHANNELS_PER_UNIVERSE - self.channel_offset) / 3
+    }
+}
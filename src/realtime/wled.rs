@@ -0,0 +1,167 @@
+use anyhow::{Result, Error};
+use serde::{Serialize, Deserialize};
+
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, UdpSocket};
+use std::time::Duration;
+
+use super::{rgb_pixels, RealtimeContext, POLL_INTERVAL};
+use crate::led_control::{Color, RealtimeSource, RealtimeUpdate};
+
+
+pub const PORT: u16 = 21324;
+
+const MAX_PACKET_SIZE: usize = 1472;
+const PROTOCOL_WARLS: u8 = 1;
+const PROTOCOL_DRGB: u8 = 2;
+const PROTOCOL_DRGBW: u8 = 3;
+const PROTOCOL_DNRGB: u8 = 4;
+/// Timeout byte that keeps the stream up until the next packet says otherwise
+const NO_TIMEOUT: u8 = 255;
+
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WledConfig {
+    pub enabled: bool,
+}
+
+impl Default for WledConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+        }
+    }
+}
+
+
+enum Packet {
+    /// Runs of neighbouring pixels and the index of the first in each
+    Pixels {
+        runs: Vec<(usize, Vec<Color>)>,
+        timeout: Duration,
+    },
+    /// A timeout of 0 hands back to the effects
+    End,
+}
+
+/// WLED's realtime protocols share a header of the protocol and a timeout in seconds. Its
+/// sync notifications, protocol 0, are ignored
+fn parse(data: &[u8]) -> Result<Packet> {
+    if data.len() < 2 {
+        return Err(Error::msg("Packet too short"))
+    }
+    let timeout = match data[1] {
+        0 => return Ok(Packet::End),
+        NO_TIMEOUT => Duration::MAX,
+        seconds => Duration::from_secs(seconds as u64),
+    };
+    let body = &data[2..];
+
+    let runs = match data[0] {
+        // Each pixel carries its own index, so they can be scattered
+        PROTOCOL_WARLS => {
+            let mut runs: Vec<(usize, Vec<Color>)> = Vec::new();
+            for pixel in body.chunks_exact(4) {
+                let (index, color) = (pixel[0] as usize, Color::rgb(pixel[1], pixel[2], pixel[3]));
+                match runs.last_mut() {
+                    Some((offset, run)) if *offset + run.len() == index => run.push(color),
+                    _ => runs.push((index, vec![color])),
+                }
+            }
+            runs
+        },
+        PROTOCOL_DRGB => vec![(0, rgb_pixels(body))],
+        PROTOCOL_DRGBW => vec![(0, body.chunks_exact(4).map(|rgbw| Color::rgbw(rgbw[0], rgbw[1], rgbw[2], rgbw[3])).collect())],
+        PROTOCOL_DNRGB => {
+            if body.len() < 2 {
+                return Err(Error::msg("DNRGB packet too short"))
+            }
+            vec![(u16::from_be_bytes([body[0], body[1]]) as usize, rgb_pixels(&body[2..]))]
+        },
+        protocol => return Err(Error::msg(format!("Unsupported protocol {protocol}"))),
+    };
+
+    Ok(Packet::Pixels { runs, timeout })
+}
+
+
+pub(super) fn listen(context: RealtimeContext) -> Result<()> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, PORT))?;
+    socket.set_read_timeout(Some(POLL_INTERVAL))?;
+    log::info!("Listening for WLED realtime on port {PORT}");
+
+    let mut buffer = [0; MAX_PACKET_SIZE];
+
+    loop {
+        let len = match socket.recv(&mut buffer) {
+            Ok(len) => len,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        if !context.config().wled.enabled {
+            continue
+        }
+
+        match parse(&buffer[..len]) {
+            // Shown as soon as each packet is written, as WLED does
+            Ok(Packet::Pixels { runs, timeout }) => {
+                let count = runs.len();
+                for (i, (offset, colors)) in runs.into_iter().enumerate() {
+                    let show = i + 1 == count;
+                    context.send(RealtimeUpdate { source: RealtimeSource::Wled, offset, colors, show, timeout })?;
+                }
+            },
+            Ok(Packet::End) => context.end(RealtimeSource::Wled)?,
+            Err(e) => log::debug!("Ignoring WLED realtime packet: {e:?}"),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Offset and RGBW colors of a run
+    type Run = (usize, Vec<[u8; 4]>);
+
+    fn pixels(data: &[u8]) -> Result<(Vec<Run>, Duration)> {
+        match parse(data)? {
+            Packet::Pixels { runs, timeout } => Ok((
+                runs.into_iter()
+                    .map(|(offset, colors)| (offset, colors.iter().map(|color| [color.r, color.g, color.b, color.w]).collect()))
+                    .collect(),
+                timeout,
+            )),
+            Packet::End => Err(Error::msg("Not a pixel packet")),
+        }
+    }
+
+    #[test]
+    fn parses_warls() -> Result<()> {
+        let (runs, timeout) = pixels(&[PROTOCOL_WARLS, 2, 3, 1, 1, 1, 4, 2, 2, 2, 9, 3, 3, 3, 0xff])?;
+        assert_eq!(runs, [(3, vec![[1, 1, 1, 0], [2, 2, 2, 0]]), (9, vec![[3, 3, 3, 0]])]);
+        assert_eq!(timeout, Duration::from_secs(2));
+        Ok(())
+    }
+
+    #[test]
+    fn parses_whole_frames() -> Result<()> {
+        assert_eq!(pixels(&[PROTOCOL_DRGB, 1, 1, 2, 3, 4])?.0, [(0, vec![[1, 2, 3, 0]])]);
+        assert_eq!(pixels(&[PROTOCOL_DRGBW, 1, 1, 2, 3, 4, 5])?.0, [(0, vec![[1, 2, 3, 4]])]);
+        assert_eq!(pixels(&[PROTOCOL_DNRGB, 1, 0x01, 0x02, 1, 2, 3])?.0, [(0x0102, vec![[1, 2, 3, 0]])]);
+        assert!(parse(&[PROTOCOL_DNRGB, 1, 0]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn reads_the_timeout() -> Result<()> {
+        assert!(matches!(parse(&[PROTOCOL_DRGB, 0, 1, 2, 3])?, Packet::End));
+        assert_eq!(pixels(&[PROTOCOL_DRGB, NO_TIMEOUT])?.1, Duration::MAX);
+        assert!(parse(&[PROTOCOL_DRGB]).is_err());
+        // Sync notifications
+        assert!(parse(&[0, 1, 0, 0]).is_err());
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{Result, Error};
 use esp_idf_svc::{
     http::server::{
         EspHttpServer,
@@ -13,10 +13,16 @@ use embedded_svc::{
 };
 use core::str;
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex, mpsc};
 use std::thread;
 use std::time::Duration;
 
 use crate::wifi::{WifiService, WifiMode};
+use crate::led_control::{LEDControllerService, ControllerCommand, TransitionConfig, OutputConfig, PixelMap, Playlist, Preset, SegmentConfig};
+use crate::effects::{EffectConfig, EFFECTS};
+use crate::realtime::{RealtimeConfig, RealtimeService};
+use crate::settings::{DeviceConfig, Settings, SettingsService, SettingsCommand};
+use crate::wled;
 use crate::ota;
 
 
@@ -25,10 +31,22 @@ pub const GIT_HASH: &str = env!("GIT_HASH");
 const LANDING_HTML: &str = include_str!("../data/landing.html");
 const FAVICON: &[u8] = include_bytes!("../data/led.ico");
 
+const MAX_URI_HANDLERS: usize = 40; // Each path and method pair takes one
+/// Room for a config import with a full pixel map
+const MAX_REQUEST_SIZE: usize = 64 * 1024;
+/// How long a config import, output change or preset save waits for the controller or the
+/// NVS write
+const APPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+const JSON_HEADERS: &[(&str, &str)] = &[("Content-Type", "application/json")];
+
 
 pub struct ServerService {
     _esp_server: EspHttpServer,
     _wifi_svc: WifiService,
+    _led_ctrl_svc: LEDControllerService,
+    _realtime_svc: RealtimeService,
+    _settings_svc: SettingsService,
 }
 
 #[derive(serde::Deserialize)]
@@ -37,9 +55,45 @@ struct WifiForm {
     password: String,
 }
 
+#[derive(serde::Deserialize)]
+struct SeedRequest {
+    seed: u64,
+}
+
+#[derive(serde::Deserialize)]
+struct EffectRequest {
+    /// Index of the segment to change
+    #[serde(default)]
+    segment: usize,
+    #[serde(default)]
+    transition: TransitionConfig,
+    #[serde(flatten)]
+    effect: EffectConfig,
+}
+
+#[derive(serde::Deserialize)]
+struct NameRequest {
+    name: String,
+}
+
+#[derive(serde::Deserialize)]
+struct PresetRequest {
+    name: String,
+    #[serde(default)]
+    transition: TransitionConfig,
+}
+
 impl ServerService {
-    pub fn init_server(wifi_svc: WifiService) -> Result<Self> {
-        let mut esp_server = EspHttpServer::new(&Configuration::default())?;
+    pub fn init_server(
+        wifi_svc: WifiService,
+        led_ctrl_svc: LEDControllerService,
+        realtime_svc: RealtimeService,
+        settings_svc: SettingsService,
+    ) -> Result<Self> {
+        let mut esp_server = EspHttpServer::new(&Configuration {
+            max_uri_handlers: MAX_URI_HANDLERS,
+            ..Default::default()
+        })?;
 
 
 
@@ -97,7 +151,14 @@ impl ServerService {
 
         let wifi_sender = wifi_svc.wifi_mode_tx.clone();
         esp_server.fn_handler("/wifi-data", Method::Post, move |mut request| {
-            let data = get_request_data(&mut request);
+            let data = match get_request_data(&mut request) {
+                Ok(data) => data,
+                Err(e) => {
+                    log::warn!("Bad request: {e}");
+                    request.into_response(400, Some(&e.to_string()), &[])?;
+                    return Ok(())
+                }
+            };
 
             if let Ok(wifi_form) = serde_urlencoded::from_bytes::<WifiForm>(&data) {
                 match wifi_sender.send(crate::wifi::WifiMode::client(&wifi_form.ssid, &wifi_form.password))
@@ -114,7 +175,950 @@ impl ServerService {
 
 
 
-        esp_server.fn_handler("/ota-update", Method::Post, |mut request| {
+        let segment_status = led_ctrl_svc.current_segments().clone();
+        esp_server.fn_handler("/api/effects", Method::Get, move |request| {
+            // The first segment's effect, for clients that only know about one
+            let current = match segment_status.lock() {
+                Ok(segments) => segments.first().map(|segment| segment.effect.clone()),
+                Err(_) => {
+                    request.into_response(500, Some("Unable to get current effect"), &[])?;
+                    return Ok(())
+                }
+            };
+
+            let body = serde_json::json!({
+                "available": EFFECTS,
+                "current": current,
+            });
+
+            let mut response = request.into_response(200, None, JSON_HEADERS)?;
+            response.write(body.to_string().as_bytes())?;
+            Ok(())
+        })?;
+
+
+
+        let controller_sender = led_ctrl_svc.controller_tx.clone();
+        esp_server.fn_handler("/api/effect", Method::Post, move |mut request| {
+            let data = match get_request_data(&mut request) {
+                Ok(data) => data,
+                Err(e) => {
+                    log::warn!("Bad request: {e}");
+                    request.into_response(400, Some(&e.to_string()), &[])?;
+                    return Ok(())
+                }
+            };
+
+            let effect_request = serde_json::from_slice::<EffectRequest>(&data)
+                .map_err(Error::from)
+                .and_then(|req| Ok((req.segment, req.effect.resolve()?, req.transition)));
+
+            match effect_request {
+                Ok((segment, config, transition)) => {
+                    match controller_sender.send(ControllerCommand::SetEffect(segment, config, transition)) {
+                        Ok(_) => request.into_ok_response()?,
+                        Err(_) => request.into_response(500, Some("Unable to send effect to controller"), &[])?
+                    };
+                },
+                Err(e) => {
+                    log::warn!("Bad effect data: {e}");
+                    request.into_response(400, Some(&e.to_string()), &[])?;
+                }
+            }
+
+            Ok(())
+        })?;
+
+
+
+        let segment_status = led_ctrl_svc.current_segments().clone();
+        esp_server.fn_handler("/api/segments", Method::Get, move |request| {
+            let current = match segment_status.lock() {
+                Ok(segments) => segments.clone(),
+                Err(_) => {
+                    request.into_response(500, Some("Unable to get current segments"), &[])?;
+                    return Ok(())
+                }
+            };
+
+            let mut response = request.into_response(200, None, JSON_HEADERS)?;
+            response.write(serde_json::to_string(&current)?.as_bytes())?;
+            Ok(())
+        })?;
+
+
+
+        let controller_sender = led_ctrl_svc.controller_tx.clone();
+        let segments_output = led_ctrl_svc.current_output().clone();
+        esp_server.fn_handler("/api/segments", Method::Post, move |mut request| {
+            let data = match get_request_data(&mut request) {
+                Ok(data) => data,
+                Err(e) => {
+                    log::warn!("Bad request: {e}");
+                    request.into_response(400, Some(&e.to_string()), &[])?;
+                    return Ok(())
+                }
+            };
+
+            let segments_request = serde_json::from_slice::<Vec<SegmentConfig>>(&data)
+                .map_err(Error::from)
+                .and_then(|mut segments| {
+                    let led_count = segments_output.lock().map_err(|_| Error::msg("Failed to unlock output mutex"))?.led_count();
+                    SegmentConfig::validate_all(&segments, led_count)?;
+                    for segment in &mut segments {
+                        segment.effect = segment.effect.clone().resolve()?;
+                    }
+                    Ok(segments)
+                });
+
+            match segments_request {
+                Ok(segments) => {
+                    match controller_sender.send(ControllerCommand::SetSegments(segments)) {
+                        Ok(_) => request.into_ok_response()?,
+                        Err(_) => request.into_response(500, Some("Unable to send segments to controller"), &[])?
+                    };
+                },
+                Err(e) => {
+                    log::warn!("Bad segments data: {e}");
+                    request.into_response(400, Some(&e.to_string()), &[])?;
+                }
+            }
+
+            Ok(())
+        })?;
+
+
+
+        let controller_sender = led_ctrl_svc.controller_tx.clone();
+        esp_server.fn_handler("/api/seed", Method::Post, move |mut request| {
+            let data = match get_request_data(&mut request) {
+                Ok(data) => data,
+                Err(e) => {
+                    log::warn!("Bad request: {e}");
+                    request.into_response(400, Some(&e.to_string()), &[])?;
+                    return Ok(())
+                }
+            };
+
+            match serde_json::from_slice::<SeedRequest>(&data) {
+                Ok(seed_request) => {
+                    match controller_sender.send(ControllerCommand::Reseed(seed_request.seed)) {
+                        Ok(_) => request.into_ok_response()?,
+                        Err(_) => request.into_response(500, Some("Unable to send seed to controller"), &[])?
+                    };
+                },
+                Err(e) => {
+                    log::warn!("Bad seed data: {e}");
+                    request.into_response(400, Some(&e.to_string()), &[])?;
+                }
+            }
+
+            Ok(())
+        })?;
+
+
+
+        let output_status = led_ctrl_svc.current_output().clone();
+        esp_server.fn_handler("/api/output", Method::Get, move |request| {
+            let current = match output_status.lock() {
+                Ok(output) => output.clone(),
+                Err(_) => {
+                    request.into_response(500, Some("Unable to get current output config"), &[])?;
+                    return Ok(())
+                }
+            };
+
+            let mut response = request.into_response(200, None, JSON_HEADERS)?;
+            response.write(serde_json::to_string(&current)?.as_bytes())?;
+            Ok(())
+        })?;
+
+
+
+        let power_status = led_ctrl_svc.power_status().clone();
+        esp_server.fn_handler("/api/power", Method::Get, move |request| {
+            let status = match power_status.lock() {
+                Ok(status) => *status,
+                Err(_) => {
+                    request.into_response(500, Some("Unable to get power status"), &[])?;
+                    return Ok(())
+                }
+            };
+
+            let mut response = request.into_response(200, None, JSON_HEADERS)?;
+            response.write(serde_json::to_string(&status)?.as_bytes())?;
+            Ok(())
+        })?;
+
+
+
+        let controller_sender = led_ctrl_svc.controller_tx.clone();
+        let settings_sender = settings_svc.settings_tx.clone();
+        let output_segments = led_ctrl_svc.current_segments().clone();
+        let output_status = led_ctrl_svc.current_output().clone();
+        esp_server.fn_handler("/api/output", Method::Post, move |mut request| {
+            let data = match get_request_data(&mut request) {
+                Ok(data) => data,
+                Err(e) => {
+                    log::warn!("Bad request: {e}");
+                    request.into_response(400, Some(&e.to_string()), &[])?;
+                    return Ok(())
+                }
+            };
+
+            // The segments have to fit the new strips too, or they would be dropped at the next boot
+            let output_request = serde_json::from_slice::<OutputConfig>(&data)
+                .map_err(Error::from)
+                .and_then(|config| config.validate().map(|_| config))
+                .and_then(|config| {
+                    let segments = output_segments.lock().map_err(|_| Error::msg("Failed to unlock segments mutex"))?;
+                    SegmentConfig::validate_all(&segments, config.led_count())?;
+                    Ok(config)
+                });
+
+            let config = match output_request {
+                Ok(config) => config,
+                Err(e) => {
+                    log::warn!("Bad output data: {e}");
+                    request.into_response(400, Some(&e.to_string()), &[])?;
+                    return Ok(())
+                }
+            };
+
+            let needs_restart = match output_status.lock() {
+                Ok(output) => config.needs_restart(&output),
+                Err(_) => {
+                    request.into_response(500, Some("Unable to get current output config"), &[])?;
+                    return Ok(())
+                }
+            };
+
+            // Pins are only set up at boot, so those changes are saved for the next one without
+            // going through the controller
+            if !needs_restart {
+                let (reply_tx, reply_rx) = mpsc::channel();
+                let applied = match controller_sender.send(ControllerCommand::SetOutput(config.clone(), Some(reply_tx))) {
+                    Ok(_) => reply_rx.recv_timeout(APPLY_TIMEOUT).unwrap_or_else(|_| Err(Error::msg("Timed out applying output config"))),
+                    Err(_) => Err(Error::msg("Unable to send output config to controller")),
+                };
+                if let Err(e) = applied {
+                    log::warn!("Unable to apply output config: {e:?}");
+                    request.into_response(500, Some(&e.to_string()), &[])?;
+                    return Ok(())
+                }
+            }
+
+            if settings_sender.send(SettingsCommand::SaveOutput(config)).is_err() {
+                request.into_response(500, Some("Output config not saved"), &[])?;
+                return Ok(())
+            }
+
+            let mut response = request.into_response(200, None, JSON_HEADERS)?;
+            response.write(serde_json::json!({ "restart": needs_restart }).to_string().as_bytes())?;
+            Ok(())
+        })?;
+
+
+
+        let map_status = led_ctrl_svc.current_map().clone();
+        esp_server.fn_handler("/api/map", Method::Get, move |request| {
+            let current = match map_status.lock() {
+                Ok(map) => map.clone(),
+                Err(_) => {
+                    request.into_response(500, Some("Unable to get current pixel map"), &[])?;
+                    return Ok(())
+                }
+            };
+
+            let mut response = request.into_response(200, None, JSON_HEADERS)?;
+            response.write(serde_json::to_string(&current)?.as_bytes())?;
+            Ok(())
+        })?;
+
+
+
+        let controller_sender = led_ctrl_svc.controller_tx.clone();
+        let map_output = led_ctrl_svc.current_output().clone();
+        esp_server.fn_handler("/api/map", Method::Post, move |mut request| {
+            let csv = request.header("Content-Type").map_or(false, |content_type| content_type.starts_with("text/csv"));
+            let data = match get_request_data(&mut request) {
+                Ok(data) => data,
+                Err(e) => {
+                    log::warn!("Bad request: {e}");
+                    request.into_response(400, Some(&e.to_string()), &[])?;
+                    return Ok(())
+                }
+            };
+
+            let map_request = if csv {
+                str::from_utf8(&data).map_err(Error::from).and_then(PixelMap::from_csv)
+            } else {
+                PixelMap::from_json(&data)
+            };
+            let map_request = map_request
+                .and_then(|map| map.validate().map(|_| map))
+                .and_then(|map| {
+                    let led_count = map_output.lock().map_err(|_| Error::msg("Failed to unlock output mutex"))?.led_count();
+                    if map.len() != led_count {
+                        return Err(Error::msg(format!("Map has {} points but there are {led_count} LEDs", map.len())))
+                    }
+                    Ok(map)
+                });
+
+            match map_request {
+                Ok(map) => {
+                    match controller_sender.send(ControllerCommand::SetMap(Some(map))) {
+                        Ok(_) => request.into_ok_response()?,
+                        Err(_) => request.into_response(500, Some("Unable to send pixel map to controller"), &[])?
+                    };
+                },
+                Err(e) => {
+                    log::warn!("Bad pixel map: {e}");
+                    request.into_response(400, Some(&e.to_string()), &[])?;
+                }
+            }
+
+            Ok(())
+        })?;
+
+
+
+        let controller_sender = led_ctrl_svc.controller_tx.clone();
+        esp_server.fn_handler("/api/map", Method::Delete, move |request| {
+            match controller_sender.send(ControllerCommand::SetMap(None)) {
+                Ok(_) => request.into_ok_response()?,
+                Err(_) => request.into_response(500, Some("Unable to send pixel map to controller"), &[])?
+            };
+
+            Ok(())
+        })?;
+
+
+
+        let library_status = led_ctrl_svc.current_library().clone();
+        let playlist_status = led_ctrl_svc.playlist_status().clone();
+        esp_server.fn_handler("/api/presets", Method::Get, move |request| {
+            let (library, playing) = match (library_status.lock(), playlist_status.lock()) {
+                (Ok(library), Ok(playing)) => (library.clone(), playing.clone()),
+                _ => {
+                    request.into_response(500, Some("Unable to get presets"), &[])?;
+                    return Ok(())
+                }
+            };
+
+            let body = serde_json::json!({
+                "presets": library.presets,
+                "playlists": library.playlists,
+                "playing": playing,
+            });
+
+            let mut response = request.into_response(200, None, JSON_HEADERS)?;
+            response.write(body.to_string().as_bytes())?;
+            Ok(())
+        })?;
+
+
+
+        let controller_sender = led_ctrl_svc.controller_tx.clone();
+        let preset_segments = led_ctrl_svc.current_segments().clone();
+        let preset_output = led_ctrl_svc.current_output().clone();
+        esp_server.fn_handler("/api/presets", Method::Post, move |mut request| {
+            let data = match get_request_data(&mut request) {
+                Ok(data) => data,
+                Err(e) => {
+                    log::warn!("Bad request: {e}");
+                    request.into_response(400, Some(&e.to_string()), &[])?;
+                    return Ok(())
+                }
+            };
+
+            // The preset checked here is the one saved, even if the segments change meanwhile
+            let preset_request = serde_json::from_slice::<NameRequest>(&data)
+                .map_err(Error::from)
+                .and_then(|req| {
+                    Preset {
+                        name: req.name,
+                        segments: preset_segments.lock().map_err(|_| Error::msg("Failed to unlock segments mutex"))?.clone(),
+                        brightness: preset_output.lock().map_err(|_| Error::msg("Failed to unlock output mutex"))?.brightness,
+                    }.resolve()
+                });
+            let preset = match preset_request {
+                Ok(preset) => preset,
+                Err(e) => {
+                    log::warn!("Bad preset data: {e}");
+                    request.into_response(400, Some(&e.to_string()), &[])?;
+                    return Ok(())
+                }
+            };
+
+            let (reply_tx, reply_rx) = mpsc::channel();
+            if controller_sender.send(ControllerCommand::SavePreset(preset, reply_tx)).is_err() {
+                request.into_response(500, Some("Unable to send preset to controller"), &[])?;
+                return Ok(())
+            }
+            match reply_rx.recv_timeout(APPLY_TIMEOUT) {
+                Ok(Ok(_)) => request.into_ok_response()?,
+                // Such as the library being full
+                Ok(Err(e)) => {
+                    log::warn!("Unable to save preset: {e}");
+                    request.into_response(400, Some(&e.to_string()), &[])?
+                },
+                Err(_) => request.into_response(500, Some("Timed out saving preset"), &[])?,
+            };
+
+            Ok(())
+        })?;
+
+
+
+        let controller_sender = led_ctrl_svc.controller_tx.clone();
+        let library_status = led_ctrl_svc.current_library().clone();
+        esp_server.fn_handler("/api/presets", Method::Delete, move |mut request| {
+            let data = match get_request_data(&mut request) {
+                Ok(data) => data,
+                Err(e) => {
+                    log::warn!("Bad request: {e}");
+                    request.into_response(400, Some(&e.to_string()), &[])?;
+                    return Ok(())
+                }
+            };
+
+            // Tried on a copy first so the reason it can't be deleted gets back to the client
+            let delete_request = serde_json::from_slice::<NameRequest>(&data)
+                .map_err(Error::from)
+                .and_then(|req| {
+                    let mut library = library_status.lock().map_err(|_| Error::msg("Failed to unlock presets mutex"))?.clone();
+                    library.delete_preset(&req.name)?;
+                    Ok(req.name)
+                });
+
+            match delete_request {
+                Ok(name) => {
+                    match controller_sender.send(ControllerCommand::DeletePreset(name)) {
+                        Ok(_) => request.into_ok_response()?,
+                        Err(_) => request.into_response(500, Some("Unable to send preset to controller"), &[])?
+                    };
+                },
+                Err(e) => {
+                    log::warn!("Bad preset data: {e}");
+                    request.into_response(400, Some(&e.to_string()), &[])?;
+                }
+            }
+
+            Ok(())
+        })?;
+
+
+
+        let controller_sender = led_ctrl_svc.controller_tx.clone();
+        let library_status = led_ctrl_svc.current_library().clone();
+        esp_server.fn_handler("/api/presets/apply", Method::Post, move |mut request| {
+            let data = match get_request_data(&mut request) {
+                Ok(data) => data,
+                Err(e) => {
+                    log::warn!("Bad request: {e}");
+                    request.into_response(400, Some(&e.to_string()), &[])?;
+                    return Ok(())
+                }
+            };
+
+            let apply_request = serde_json::from_slice::<PresetRequest>(&data)
+                .map_err(Error::from)
+                .and_then(|req| {
+                    let library = library_status.lock().map_err(|_| Error::msg("Failed to unlock presets mutex"))?;
+                    if library.preset(&req.name).is_none() {
+                        return Err(Error::msg(format!("No preset {}", req.name)))
+                    }
+                    Ok(req)
+                });
+
+            match apply_request {
+                Ok(req) => {
+                    match controller_sender.send(ControllerCommand::ApplyPreset(req.name, req.transition)) {
+                        Ok(_) => request.into_ok_response()?,
+                        Err(_) => request.into_response(500, Some("Unable to send preset to controller"), &[])?
+                    };
+                },
+                Err(e) => {
+                    log::warn!("Bad preset data: {e}");
+                    request.into_response(400, Some(&e.to_string()), &[])?;
+                }
+            }
+
+            Ok(())
+        })?;
+
+
+
+        let controller_sender = led_ctrl_svc.controller_tx.clone();
+        let library_status = led_ctrl_svc.current_library().clone();
+        esp_server.fn_handler("/api/playlists", Method::Post, move |mut request| {
+            let data = match get_request_data(&mut request) {
+                Ok(data) => data,
+                Err(e) => {
+                    log::warn!("Bad request: {e}");
+                    request.into_response(400, Some(&e.to_string()), &[])?;
+                    return Ok(())
+                }
+            };
+
+            // Tried on a copy first so the reason it can't be saved gets back to the client
+            let playlist_request = serde_json::from_slice::<Playlist>(&data)
+                .map_err(Error::from)
+                .and_then(|playlist| {
+                    let mut library = library_status.lock().map_err(|_| Error::msg("Failed to unlock presets mutex"))?.clone();
+                    library.save_playlist(playlist.clone())?;
+                    Ok(playlist)
+                });
+
+            match playlist_request {
+                Ok(playlist) => {
+                    match controller_sender.send(ControllerCommand::SavePlaylist(playlist)) {
+                        Ok(_) => request.into_ok_response()?,
+                        Err(_) => request.into_response(500, Some("Unable to send playlist to controller"), &[])?
+                    };
+                },
+                Err(e) => {
+                    log::warn!("Bad playlist data: {e}");
+                    request.into_response(400, Some(&e.to_string()), &[])?;
+                }
+            }
+
+            Ok(())
+        })?;
+
+
+
+        let controller_sender = led_ctrl_svc.controller_tx.clone();
+        let library_status = led_ctrl_svc.current_library().clone();
+        esp_server.fn_handler("/api/playlists", Method::Delete, move |mut request| {
+            let data = match get_request_data(&mut request) {
+                Ok(data) => data,
+                Err(e) => {
+                    log::warn!("Bad request: {e}");
+                    request.into_response(400, Some(&e.to_string()), &[])?;
+                    return Ok(())
+                }
+            };
+
+            let delete_request = serde_json::from_slice::<NameRequest>(&data)
+                .map_err(Error::from)
+                .and_then(|req| {
+                    let mut library = library_status.lock().map_err(|_| Error::msg("Failed to unlock presets mutex"))?.clone();
+                    library.delete_playlist(&req.name)?;
+                    Ok(req.name)
+                });
+
+            match delete_request {
+                Ok(name) => {
+                    match controller_sender.send(ControllerCommand::DeletePlaylist(name)) {
+                        Ok(_) => request.into_ok_response()?,
+                        Err(_) => request.into_response(500, Some("Unable to send playlist to controller"), &[])?
+                    };
+                },
+                Err(e) => {
+                    log::warn!("Bad playlist data: {e}");
+                    request.into_response(400, Some(&e.to_string()), &[])?;
+                }
+            }
+
+            Ok(())
+        })?;
+
+
+
+        let controller_sender = led_ctrl_svc.controller_tx.clone();
+        let library_status = led_ctrl_svc.current_library().clone();
+        esp_server.fn_handler("/api/playlists/play", Method::Post, move |mut request| {
+            let data = match get_request_data(&mut request) {
+                Ok(data) => data,
+                Err(e) => {
+                    log::warn!("Bad request: {e}");
+                    request.into_response(400, Some(&e.to_string()), &[])?;
+                    return Ok(())
+                }
+            };
+
+            let play_request = serde_json::from_slice::<NameRequest>(&data)
+                .map_err(Error::from)
+                .and_then(|req| {
+                    let library = library_status.lock().map_err(|_| Error::msg("Failed to unlock presets mutex"))?;
+                    if library.playlist(&req.name).is_none() {
+                        return Err(Error::msg(format!("No playlist {}", req.name)))
+                    }
+                    Ok(req.name)
+                });
+
+            match play_request {
+                Ok(name) => {
+                    match controller_sender.send(ControllerCommand::PlayPlaylist(name)) {
+                        Ok(_) => request.into_ok_response()?,
+                        Err(_) => request.into_response(500, Some("Unable to send playlist to controller"), &[])?
+                    };
+                },
+                Err(e) => {
+                    log::warn!("Bad playlist data: {e}");
+                    request.into_response(400, Some(&e.to_string()), &[])?;
+                }
+            }
+
+            Ok(())
+        })?;
+
+
+
+        let controller_sender = led_ctrl_svc.controller_tx.clone();
+        esp_server.fn_handler("/api/playlists/stop", Method::Post, move |request| {
+            match controller_sender.send(ControllerCommand::StopPlaylist) {
+                Ok(_) => request.into_ok_response()?,
+                Err(_) => request.into_response(500, Some("Unable to send playlist to controller"), &[])?
+            };
+
+            Ok(())
+        })?;
+
+
+
+        let realtime_config = realtime_svc.current_config().clone();
+        let realtime_status = led_ctrl_svc.realtime_status().clone();
+        esp_server.fn_handler("/api/realtime", Method::Get, move |request| {
+            let body = match (realtime_config.lock(), realtime_status.lock()) {
+                (Ok(config), Ok(active)) => {
+                    let mut body = serde_json::to_value(&*config)?;
+                    body["active"] = serde_json::to_value(*active)?;
+                    body
+                },
+                _ => {
+                    request.into_response(500, Some("Unable to get realtime config"), &[])?;
+                    return Ok(())
+                }
+            };
+
+            let mut response = request.into_response(200, None, JSON_HEADERS)?;
+            response.write(body.to_string().as_bytes())?;
+            Ok(())
+        })?;
+
+
+
+        let realtime_config = realtime_svc.current_config().clone();
+        esp_server.fn_handler("/api/realtime", Method::Post, move |mut request| {
+            let data = match get_request_data(&mut request) {
+                Ok(data) => data,
+                Err(e) => {
+                    log::warn!("Bad request: {e}");
+                    request.into_response(400, Some(&e.to_string()), &[])?;
+                    return Ok(())
+                }
+            };
+
+            let realtime_request = serde_json::from_slice::<RealtimeConfig>(&data)
+                .map_err(Error::from)
+                .and_then(|config| config.validate().map(|_| config));
+
+            match realtime_request {
+                Ok(config) => {
+                    match realtime_config.lock() {
+                        Ok(mut current) => {
+                            *current = config;
+                            request.into_ok_response()?
+                        },
+                        Err(_) => request.into_response(500, Some("Unable to set realtime config"), &[])?
+                    };
+                },
+                Err(e) => {
+                    log::warn!("Bad realtime data: {e}");
+                    request.into_response(400, Some(&e.to_string()), &[])?;
+                }
+            }
+
+            Ok(())
+        })?;
+
+
+
+        let config_wifi = wifi_svc.current_mode().clone();
+        let config_output = led_ctrl_svc.current_output().clone();
+        let config_segments = led_ctrl_svc.current_segments().clone();
+        let config_library = led_ctrl_svc.current_library().clone();
+        let config_map = led_ctrl_svc.current_map().clone();
+        let config_realtime = realtime_svc.current_config().clone();
+        esp_server.fn_handler("/api/config", Method::Get, move |request| {
+            let current = match (config_output.lock(), config_segments.lock(), config_library.lock(), config_map.lock(), config_realtime.lock()) {
+                (Ok(output), Ok(segments), Ok(library), Ok(map), Ok(realtime)) => DeviceConfig {
+                    settings: Settings {
+                        output: output.clone(),
+                        segments: segments.clone(),
+                        library: library.clone(),
+                        realtime: realtime.clone(),
+                    },
+                    map: map.clone(),
+                },
+                _ => {
+                    request.into_response(500, Some("Unable to get current config"), &[])?;
+                    return Ok(())
+                }
+            };
+
+            // The SSID is handy for telling boards apart, the password stays on the device
+            let wifi = match config_wifi.lock().as_deref() {
+                Ok(WifiMode::AP) => serde_json::json!({ "mode": "ap" }),
+                Ok(WifiMode::Client(client)) => serde_json::json!({ "mode": "client", "ssid": client.ssid.as_str() }),
+                Err(_) => serde_json::Value::Null,
+            };
+            let mut body = current.to_json()?;
+            body["wifi"] = wifi;
+
+            let mut response = request.into_response(200, None, JSON_HEADERS)?;
+            response.write(body.to_string().as_bytes())?;
+            Ok(())
+        })?;
+
+
+
+        let controller_sender = led_ctrl_svc.controller_tx.clone();
+        let settings_sender = settings_svc.settings_tx.clone();
+        let config_output = led_ctrl_svc.current_output().clone();
+        let config_realtime = realtime_svc.current_config().clone();
+        esp_server.fn_handler("/api/config", Method::Post, move |mut request| {
+            let data = match get_request_data(&mut request) {
+                Ok(data) => data,
+                Err(e) => {
+                    log::warn!("Bad request: {e}");
+                    request.into_response(400, Some(&e.to_string()), &[])?;
+                    return Ok(())
+                }
+            };
+
+            let config = match DeviceConfig::from_json(&data) {
+                Ok(config) => config,
+                Err(e) => {
+                    log::warn!("Bad config data: {e}");
+                    request.into_response(400, Some(&e.to_string()), &[])?;
+                    return Ok(())
+                }
+            };
+
+            let needs_restart = match config_output.lock() {
+                Ok(output) => config.settings.output.needs_restart(&output),
+                Err(_) => {
+                    request.into_response(500, Some("Unable to get current output config"), &[])?;
+                    return Ok(())
+                }
+            };
+
+            // Pins are only set up at boot, so a config that changes them is saved and the
+            // board restarted rather than applying half of it
+            let realtime = config.settings.realtime.clone();
+            let (reply_tx, reply_rx) = mpsc::channel();
+            let sent = if needs_restart {
+                settings_sender.send(SettingsCommand::Replace(Box::new(config), reply_tx)).is_ok()
+            } else {
+                controller_sender.send(ControllerCommand::SetConfig(Box::new(config.into()), reply_tx)).is_ok()
+            };
+            let applied = if sent {
+                reply_rx.recv_timeout(APPLY_TIMEOUT).unwrap_or_else(|_| Err(Error::msg("Timed out applying config")))
+            } else {
+                Err(Error::msg("Unable to apply config"))
+            };
+            if let Err(e) = applied {
+                log::warn!("Unable to apply config: {e:?}");
+                request.into_response(500, Some(&e.to_string()), &[])?;
+                return Ok(())
+            }
+
+            // Only once the rest has been applied, so a failed import changes nothing
+            if !needs_restart {
+                match config_realtime.lock() {
+                    Ok(mut current) => *current = realtime,
+                    Err(_) => {
+                        request.into_response(500, Some("Unable to set realtime config"), &[])?;
+                        return Ok(())
+                    }
+                }
+            }
+
+            let mut response = request.into_response(200, None, JSON_HEADERS)?;
+            response.write(serde_json::json!({ "restart": needs_restart }).to_string().as_bytes())?;
+
+            // The config is already saved, this only gives the response time to go out
+            if needs_restart {
+                thread::spawn(|| {
+                    thread::sleep(Duration::from_secs(1));
+                    esp_idf_hal::reset::restart();
+                });
+            }
+
+            Ok(())
+        })?;
+
+
+
+        // Brightness to go back to when a WLED client turns the strips on again, since WLED
+        // keeps on/off apart from brightness and our output config doesn't
+        let last_brightness = match led_ctrl_svc.current_output().lock() {
+            Ok(output) if output.brightness > 0 => output.brightness,
+            _ => OutputConfig::default().brightness,
+        };
+        let last_brightness = Arc::new(Mutex::new(last_brightness));
+
+
+
+        // Stays the same while running, so is only read once
+        let mut mac = [0u8; 6];
+        esp_idf_sys::esp!(unsafe { esp_idf_sys::esp_read_mac(mac.as_mut_ptr(), esp_idf_sys::esp_mac_type_t_ESP_MAC_WIFI_STA) })?;
+
+
+
+        let state_output = led_ctrl_svc.current_output().clone();
+        let state_segments = led_ctrl_svc.current_segments().clone();
+        let state_library = led_ctrl_svc.current_library().clone();
+        let state_preset = led_ctrl_svc.applied_preset().clone();
+        let state_playlist = led_ctrl_svc.playlist_status().clone();
+        let state_brightness = last_brightness.clone();
+        esp_server.fn_handler("/json/state", Method::Get, move |request| {
+            let locks = (state_output.lock(), state_segments.lock(), state_library.lock(), state_preset.lock(), state_playlist.lock(), state_brightness.lock());
+            let state = match locks {
+                (Ok(output), Ok(segments), Ok(library), Ok(preset), Ok(playlist), Ok(brightness)) => {
+                    wled::State::new(&output, &segments, &library, preset.as_deref(), playlist.as_ref(), *brightness)
+                },
+                _ => {
+                    request.into_response(500, Some("Unable to get current state"), &[])?;
+                    return Ok(())
+                }
+            };
+
+            let mut response = request.into_response(200, None, JSON_HEADERS)?;
+            response.write(serde_json::to_string(&state)?.as_bytes())?;
+            Ok(())
+        })?;
+
+
+
+        let controller_sender = led_ctrl_svc.controller_tx.clone();
+        let state_output = led_ctrl_svc.current_output().clone();
+        let state_segments = led_ctrl_svc.current_segments().clone();
+        let state_library = led_ctrl_svc.current_library().clone();
+        let state_preset = led_ctrl_svc.applied_preset().clone();
+        let state_playlist = led_ctrl_svc.playlist_status().clone();
+        let state_brightness = last_brightness.clone();
+        esp_server.fn_handler("/json/state", Method::Post, move |mut request| {
+            let data = match get_request_data(&mut request) {
+                Ok(data) => data,
+                Err(e) => {
+                    log::warn!("Bad request: {e}");
+                    request.into_response(400, Some(&e.to_string()), &[])?;
+                    return Ok(())
+                }
+            };
+
+            let state_request = serde_json::from_slice::<wled::StateUpdate>(&data)
+                .map_err(Error::from)
+                .and_then(|update| {
+                    let verbose = update.v;
+                    let locks = (state_output.lock(), state_segments.lock(), state_library.lock(), state_preset.lock(), state_playlist.lock(), state_brightness.lock());
+                    let (Ok(output), Ok(segments), Ok(library), Ok(preset), Ok(playlist), Ok(mut brightness)) = locks else {
+                        return Err(Error::msg("Unable to get current state"))
+                    };
+                    if output.brightness > 0 {
+                        *brightness = output.brightness;
+                    }
+                    let (commands, state) = update.apply(&output, &segments, &library, preset.as_deref(), playlist.as_ref(), *brightness)?;
+                    Ok((commands, verbose.then_some(state)))
+                });
+
+            match state_request {
+                Ok((commands, state)) => {
+                    if commands.into_iter().any(|command| controller_sender.send(command).is_err()) {
+                        request.into_response(500, Some("Unable to send state to controller"), &[])?;
+                        return Ok(())
+                    }
+
+                    // WLED only sends the state back when asked to with "v"
+                    let body = match state {
+                        Some(state) => serde_json::to_string(&state)?,
+                        None => serde_json::json!({ "success": true }).to_string(),
+                    };
+                    let mut response = request.into_response(200, None, JSON_HEADERS)?;
+                    response.write(body.as_bytes())?;
+                },
+                Err(e) => {
+                    log::warn!("Bad WLED state data: {e}");
+                    request.into_response(400, Some(&e.to_string()), &[])?;
+                }
+            }
+
+            Ok(())
+        })?;
+
+
+
+        let info_output = led_ctrl_svc.current_output().clone();
+        let info_power = led_ctrl_svc.power_status().clone();
+        let info_realtime = led_ctrl_svc.realtime_status().clone();
+        esp_server.fn_handler("/json/info", Method::Get, move |request| {
+            let uptime = unsafe { esp_idf_sys::esp_timer_get_time() } as u64 / 1_000_000;
+            let freeheap = unsafe { esp_idf_sys::esp_get_free_heap_size() };
+
+            let info = match (info_output.lock(), info_power.lock(), info_realtime.lock()) {
+                (Ok(output), Ok(power), Ok(realtime)) => wled::Info::new(&output, &power, *realtime, uptime, freeheap, mac),
+                _ => {
+                    request.into_response(500, Some("Unable to get device info"), &[])?;
+                    return Ok(())
+                }
+            };
+
+            let mut response = request.into_response(200, None, JSON_HEADERS)?;
+            response.write(serde_json::to_string(&info)?.as_bytes())?;
+            Ok(())
+        })?;
+
+
+
+        esp_server.fn_handler("/json/effects", Method::Get, move |request| {
+            let mut response = request.into_response(200, None, JSON_HEADERS)?;
+            response.write(serde_json::to_string(&wled::effect_names())?.as_bytes())?;
+            Ok(())
+        })?;
+
+
+
+        let json_output = led_ctrl_svc.current_output().clone();
+        let json_segments = led_ctrl_svc.current_segments().clone();
+        let json_library = led_ctrl_svc.current_library().clone();
+        let json_preset = led_ctrl_svc.applied_preset().clone();
+        let json_playlist = led_ctrl_svc.playlist_status().clone();
+        let json_brightness = last_brightness.clone();
+        let json_power = led_ctrl_svc.power_status().clone();
+        let json_realtime = led_ctrl_svc.realtime_status().clone();
+        esp_server.fn_handler("/json", Method::Get, move |request| {
+            let uptime = unsafe { esp_idf_sys::esp_timer_get_time() } as u64 / 1_000_000;
+            let freeheap = unsafe { esp_idf_sys::esp_get_free_heap_size() };
+
+            let state_locks = (json_output.lock(), json_segments.lock(), json_library.lock(), json_preset.lock(), json_playlist.lock(), json_brightness.lock());
+            let info_locks = (json_power.lock(), json_realtime.lock());
+            let everything = match (state_locks, info_locks) {
+                ((Ok(output), Ok(segments), Ok(library), Ok(preset), Ok(playlist), Ok(brightness)), (Ok(power), Ok(realtime))) => wled::Everything {
+                    state: wled::State::new(&output, &segments, &library, preset.as_deref(), playlist.as_ref(), *brightness),
+                    info: wled::Info::new(&output, &power, *realtime, uptime, freeheap, mac),
+                    effects: wled::effect_names(),
+                    palettes: wled::palette_names(),
+                },
+                _ => {
+                    request.into_response(500, Some("Unable to get current state"), &[])?;
+                    return Ok(())
+                }
+            };
+
+            let mut response = request.into_response(200, None, JSON_HEADERS)?;
+            response.write(serde_json::to_string(&everything)?.as_bytes())?;
+            Ok(())
+        })?;
+
+
+
+        let settings_sender = settings_svc.settings_tx.clone();
+        esp_server.fn_handler("/ota-update", Method::Post, move |mut request| {
             if request.header("X-Requested-With").is_none() {
                 log::warn!("ota-update POST without X-Requested-With header");
                 request.into_status_response(406)?;
@@ -133,6 +1137,8 @@ impl ServerService {
                 }
             };
 
+            // Don't lose changes still waiting to be saved
+            let _ = settings_sender.send(SettingsCommand::Flush);
             thread::spawn(|| {
                 thread::sleep(Duration::from_secs(5));
                 esp_idf_hal::reset::restart();
@@ -146,24 +1152,36 @@ impl ServerService {
         Ok(Self {
             _esp_server: esp_server,
             _wifi_svc: wifi_svc,
+            _led_ctrl_svc: led_ctrl_svc,
+            _realtime_svc: realtime_svc,
+            _settings_svc: settings_svc,
         })
     }
 
 }
 
 
-fn get_request_data(request: &mut Request<&mut EspHttpConnection>) -> Vec<u8> {
-    let mut output = Vec::new();
+/// Reads the whole body, refusing any larger than `MAX_REQUEST_SIZE`
+fn get_request_data(request: &mut Request<&mut EspHttpConnection>) -> Result<Vec<u8>> {
+    let too_large = || Error::msg(format!("Request body is over {MAX_REQUEST_SIZE} bytes"));
+    let length = request.header("Content-Length").and_then(|length| length.parse::<usize>().ok());
+    if length.map_or(false, |length| length > MAX_REQUEST_SIZE) {
+        return Err(too_large())
+    }
+
+    let mut output = Vec::with_capacity(length.unwrap_or_default());
     let mut buffer: [u8; 256] = [0; 256];
 
     loop {
-        if let Ok(size) = request.read(&mut buffer) {
-            if size == 0 { break }
-            output.extend_from_slice(&buffer[..size])
+        let size = request.read(&mut buffer).map_err(|e| Error::msg(format!("Unable to read request body: {e:?}")))?;
+        if size == 0 { break }
+        if output.len() + size > MAX_REQUEST_SIZE {
+            return Err(too_large())
         }
+        output.extend_from_slice(&buffer[..size])
     }
 
-    output
+    Ok(output)
 }
 
 
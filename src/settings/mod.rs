@@ -0,0 +1,254 @@
+use anyhow::{Result, Error};
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+use serde_json::Value;
+
+use crate::led_control::{DeviceState, OutputConfig, PixelMap, PresetLibrary, SegmentConfig};
+use crate::realtime::RealtimeConfig;
+
+#[cfg(target_os = "espidf")]
+mod store;
+#[cfg(target_os = "espidf")]
+pub use store::{SettingsStore, SettingsService, SettingsCommand};
+
+
+/// Bumped whenever a change to `Settings` can't be read by the old code, with a migration
+/// added to `MIGRATIONS`
+pub const VERSION: u32 = 1;
+
+type Migration = fn(&mut Value) -> Result<()>;
+
+/// Upgrades settings saved by older firmware. The entry at index `i` takes version `i + 1`
+/// to `i + 2`
+const MIGRATIONS: &[Migration] = &[];
+
+
+/// Everything restored at boot
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub output: OutputConfig,
+    pub segments: Vec<SegmentConfig>,
+    #[serde(flatten)]
+    pub library: PresetLibrary,
+    pub realtime: RealtimeConfig,
+}
+
+#[derive(Serialize)]
+struct Versioned<'a, T> {
+    version: u32,
+    #[serde(flatten)]
+    value: &'a T,
+}
+
+impl Settings {
+    /// Checks everything and fills in defaults for missing effect parameters
+    pub fn resolve(mut self) -> Result<Self> {
+        self.output.validate()?;
+        SegmentConfig::validate_all(&self.segments, self.output.led_count())?;
+        for segment in &mut self.segments {
+            segment.effect = segment.effect.clone().resolve()?;
+        }
+        self.library = self.library.resolve()?;
+        self.realtime.validate()?;
+        Ok(self)
+    }
+
+    pub fn to_json(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(&Versioned { version: VERSION, value: self })?)
+    }
+
+    /// Reads settings saved by this or any older version. A section that can't be used falls
+    /// back to its defaults without losing the others
+    pub fn from_json(data: &[u8]) -> Result<Self> {
+        let value = migrate(serde_json::from_slice(data)?)?;
+        let defaults = Settings::default();
+
+        let output = section("output", value.get("output"), defaults.output, |output: OutputConfig| {
+            output.validate()?;
+            Ok(output)
+        });
+        let segments = section("segments", value.get("segments"), defaults.segments, |mut segments: Vec<SegmentConfig>| {
+            SegmentConfig::validate_all(&segments, output.led_count())?;
+            for segment in &mut segments {
+                segment.effect = segment.effect.clone().resolve()?;
+            }
+            Ok(segments)
+        });
+        // Flattened into the top level
+        let library = section("presets and playlists", Some(&value), defaults.library, PresetLibrary::resolve);
+        let realtime = section("realtime config", value.get("realtime"), defaults.realtime, |realtime: RealtimeConfig| {
+            realtime.validate()?;
+            Ok(realtime)
+        });
+
+        Ok(Self { output, segments, library, realtime })
+    }
+}
+
+
+/// Presets and playlists are saved apart from the rest of the settings, versioned the same way
+pub fn library_to_json(library: &PresetLibrary) -> Result<Vec<u8>> {
+    Ok(serde_json::to_vec(&Versioned { version: VERSION, value: library })?)
+}
+
+pub fn library_from_json(data: &[u8]) -> Result<PresetLibrary> {
+    serde_json::from_value::<PresetLibrary>(migrate(serde_json::from_slice(data)?)?)?.resolve()
+}
+
+
+/// One section of saved settings, or `fallback` if it is missing or can't be used
+fn section<T: DeserializeOwned>(name: &str, value: Option<&Value>, fallback: T, check: impl FnOnce(T) -> Result<T>) -> T {
+    let Some(value) = value else {
+        return fallback
+    };
+    match serde_json::from_value(value.clone()).map_err(Error::from).and_then(check) {
+        Ok(section) => section,
+        Err(e) => {
+            log::warn!("Ignoring saved {name}: {e:?}");
+            fallback
+        }
+    }
+}
+
+
+/// The whole setup of a device, for copying onto another board. Wi-Fi details are added by
+/// the server when exporting and ignored when importing
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DeviceConfig {
+    #[serde(flatten)]
+    pub settings: Settings,
+    #[serde(default)]
+    pub map: Option<PixelMap>,
+}
+
+impl DeviceConfig {
+    pub fn to_json(&self) -> Result<Value> {
+        Ok(serde_json::to_value(Versioned { version: VERSION, value: self })?)
+    }
+
+    /// Reads and checks a config exported by this or any older version
+    pub fn from_json(data: &[u8]) -> Result<Self> {
+        let mut config = serde_json::from_value::<DeviceConfig>(migrate(serde_json::from_slice(data)?)?)?;
+        config.settings = config.settings.resolve()?;
+
+        if let Some(map) = &config.map {
+            map.validate()?;
+            let led_count = config.settings.output.led_count();
+            if map.len() != led_count {
+                return Err(Error::msg(format!("Map has {} points but there are {led_count} LEDs", map.len())))
+            }
+        }
+        Ok(config)
+    }
+}
+
+impl From<DeviceConfig> for DeviceState {
+    fn from(config: DeviceConfig) -> Self {
+        Self {
+            output: config.settings.output,
+            segments: config.settings.segments,
+            map: config.map,
+            library: config.settings.library,
+        }
+    }
+}
+
+
+/// Upgrades a versioned document to the current version
+fn migrate(mut value: Value) -> Result<Value> {
+    let version = value.get("version").and_then(Value::as_u64)
+        .ok_or_else(|| Error::msg("Settings have no version"))? as u32;
+
+    if version == 0 || version > VERSION {
+        return Err(Error::msg(format!("Can't read settings version {version}, only up to {VERSION}")))
+    }
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize - 1) {
+        log::info!("Migrating settings from version {}", from + 1);
+        migration(&mut value)?;
+    }
+
+    Ok(value)
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            output: OutputConfig::default(),
+            // The default spooky eyes look best warmed up
+            segments: vec![SegmentConfig { white_balance: [255, 224, 140], ..Default::default() }],
+            library: PresetLibrary::default(),
+            realtime: RealtimeConfig::default(),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Written by the first firmware to version its settings
+    const V1: &str = r#"{
+        "version": 1,
+        "output": {
+            "strips": [{"pin": 16, "led_count": 60, "chipset": "ws2812b", "color_order": null, "white_mode": "none", "global_brightness": 31}],
+            "brightness": 128,
+            "gamma": [2.2, 2.2, 2.2],
+            "white_balance": [255, 255, 255],
+            "power": {"max_milliamps": 0, "milliamps_per_channel": 20.0, "idle_milliamps": 1.0}
+        },
+        "segments": [{"name": "desk", "start": 10, "length": 20, "effect": {"name": "rainbow"}}],
+        "presets": [{"name": "calm", "segments": [{"name": "all", "effect": {"name": "blink"}}], "brightness": 40}],
+        "playlists": [{"name": "evening", "entries": [{"preset": "calm", "duration_ms": 60000}]}],
+        "realtime": {"timeout_ms": 5000}
+    }"#;
+
+    #[test]
+    fn reads_v1_settings() -> Result<()> {
+        let settings = Settings::from_json(V1.as_bytes())?;
+
+        assert_eq!(settings.output.strips[0].pin, 16);
+        assert_eq!(settings.output.led_count(), 60);
+        assert_eq!(settings.output.brightness, 128);
+        assert_eq!(settings.segments[0].name, "desk");
+        assert_eq!(settings.segments[0].start, 10);
+        // Effect parameters are filled in from the defaults
+        assert_eq!(settings.segments[0].effect.params.int("deg_per_tick")?, 10);
+        assert_eq!(settings.library.preset("calm").map(|preset| preset.brightness), Some(40));
+        assert!(settings.library.playlist("evening").is_some());
+        assert_eq!(settings.realtime.timeout_ms, 5000);
+
+        // And it comes back the same once saved again
+        assert_eq!(Settings::from_json(&settings.to_json()?)?, settings);
+        Ok(())
+    }
+
+    #[test]
+    fn falls_back_per_section() -> Result<()> {
+        let mut value: Value = serde_json::from_str(V1)?;
+        value["segments"][0]["start"] = 60.into();
+        value["realtime"]["timeout_ms"] = 0.into();
+
+        let settings = Settings::from_json(&serde_json::to_vec(&value)?)?;
+        assert_eq!(settings.segments, Settings::default().segments);
+        assert_eq!(settings.realtime, RealtimeConfig::default());
+        assert_eq!(settings.output.led_count(), 60);
+        assert!(settings.library.preset("calm").is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn reads_back_the_library() -> Result<()> {
+        let settings = Settings::from_json(V1.as_bytes())?;
+        assert_eq!(library_from_json(&library_to_json(&settings.library)?)?, settings.library);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        for version in [0, VERSION + 1] {
+            assert!(Settings::from_json(format!(r#"{{"version": {version}}}"#).as_bytes()).is_err());
+        }
+        assert!(Settings::from_json(b"{}").is_err());
+    }
+}
@@ -0,0 +1,348 @@
+use anyhow::{Result, Error};
+use esp_idf_svc::nvs::{EspNvs, EspNvsPartition, NvsDefault};
+
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{DeviceConfig, Settings, library_from_json, library_to_json};
+use crate::led_control::{LEDControllerService, OutputConfig, PixelMap, Point, PresetLibrary, SegmentConfig};
+use crate::realtime::{RealtimeConfig, RealtimeService};
+
+
+const NAMESPACE: &str = "led_ctrl";
+const SETTINGS_KEY: &str = "settings";
+const LEGACY_OUTPUT_KEY: &str = "output"; // Output config saved before settings were versioned
+const LIBRARY_KEY: &str = "library";
+const MAP_KEY: &str = "pixel_map";
+/// Largest JSON value that is saved, and so the most that is read back. Leaves room for the
+/// version around a library of `PresetLibrary::MAX_SIZE`
+const MAX_VALUE_SIZE: usize = 8192;
+const POINT_SIZE: usize = 12; // x, y and z as little endian f32
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How long settings have to stay the same before they are written, so dragging a slider
+/// doesn't wear out the flash
+const DEBOUNCE: Duration = Duration::from_secs(5);
+
+const CONTROLLER_MUTEX_ERR: &str = "Failed to unlock controller state mutex";
+const REALTIME_MUTEX_ERR: &str = "Failed to unlock realtime config mutex";
+
+
+/// Settings kept in NVS across restarts, stored as JSON apart from the pixel map
+pub struct SettingsStore {
+    nvs: EspNvs<NvsDefault>,
+}
+
+
+impl SettingsStore {
+    pub fn new(partition: EspNvsPartition<NvsDefault>) -> Result<Self> {
+        Ok(Self {
+            nvs: EspNvs::new(partition, NAMESPACE, true)?,
+        })
+    }
+
+    /// The saved settings, upgraded to the current version. Falls back to the defaults for
+    /// anything that can't be used
+    pub fn load(&mut self) -> Settings {
+        let mut settings = self.load_settings();
+
+        // Older firmware kept the presets and playlists in with the rest, which are used
+        // until the library is next saved
+        if let Some(data) = self.read(LIBRARY_KEY, MAX_VALUE_SIZE) {
+            match library_from_json(&data) {
+                Ok(library) => settings.library = library,
+                Err(e) => log::warn!("Ignoring saved presets and playlists: {e:?}"),
+            }
+        }
+        settings
+    }
+
+    fn load_settings(&mut self) -> Settings {
+        if let Some(data) = self.read(SETTINGS_KEY, MAX_VALUE_SIZE) {
+            match Settings::from_json(&data) {
+                Ok(settings) => return settings,
+                Err(e) => log::warn!("Ignoring saved settings: {e:?}"),
+            }
+        }
+
+        let Some(data) = self.read(LEGACY_OUTPUT_KEY, MAX_VALUE_SIZE) else {
+            return Settings::default()
+        };
+        let legacy = serde_json::from_slice::<OutputConfig>(&data).map_err(Error::from)
+            .and_then(|output| Settings { output, ..Default::default() }.resolve());
+        match legacy {
+            Ok(settings) => {
+                log::info!("Moving saved output config into settings");
+                if let Err(e) = self.save(&settings).and_then(|_| Ok(self.nvs.remove(LEGACY_OUTPUT_KEY)?)) {
+                    log::warn!("Unable to move saved output config: {e:?}");
+                }
+                settings
+            },
+            Err(e) => {
+                log::warn!("Ignoring saved output config: {e:?}");
+                Settings::default()
+            }
+        }
+    }
+
+    /// The library has a key of its own so each value stays small enough to read back
+    pub fn save(&mut self, settings: &Settings) -> Result<()> {
+        let rest = Settings { library: PresetLibrary::default(), ..settings.clone() }.to_json()?;
+        let library = library_to_json(&settings.library)?;
+        for (key, data) in [(SETTINGS_KEY, &rest), (LIBRARY_KEY, &library)] {
+            if data.len() > MAX_VALUE_SIZE {
+                return Err(Error::msg(format!("Saved {key} would be {} bytes, over the {MAX_VALUE_SIZE} that can be read back", data.len())))
+            }
+        }
+
+        self.nvs.set_raw(SETTINGS_KEY, &rest)?;
+        self.nvs.set_raw(LIBRARY_KEY, &library)?;
+        Ok(())
+    }
+
+    /// The saved pixel map, or None if there isn't one
+    pub fn load_map(&self) -> Option<PixelMap> {
+        let data = self.read(MAP_KEY, PixelMap::MAX_POINTS * POINT_SIZE)?;
+
+        let points = data.chunks_exact(POINT_SIZE)
+            .map(|point| {
+                let value = |i: usize| f32::from_le_bytes(point[i * 4..i * 4 + 4].try_into().unwrap());
+                Point::new(value(0), value(1), value(2))
+            })
+            .collect();
+        let map = PixelMap::new(points);
+
+        match map.validate() {
+            Ok(_) => Some(map),
+            Err(e) => {
+                log::warn!("Ignoring saved pixel map: {e:?}");
+                None
+            }
+        }
+    }
+
+    /// Packed as binary since maps can be too big to fit in NVS as JSON
+    pub fn save_map(&mut self, map: Option<&PixelMap>) -> Result<()> {
+        match map {
+            Some(map) => {
+                let data: Vec<u8> = map.points().iter()
+                    .flat_map(|point| [point.x, point.y, point.z])
+                    .flat_map(f32::to_le_bytes)
+                    .collect();
+                self.nvs.set_raw(MAP_KEY, &data)?;
+            },
+            None => {
+                self.nvs.remove(MAP_KEY)?;
+            },
+        }
+        Ok(())
+    }
+
+    fn read(&self, key: &str, max_size: usize) -> Option<Vec<u8>> {
+        let mut buffer = vec![0; max_size];
+        match self.nvs.get_raw(key, &mut buffer) {
+            Ok(Some(data)) => Some(data.to_vec()),
+            Ok(None) => None,
+            Err(e) => {
+                log::warn!("Unable to read saved {key}: {e:?}");
+                None
+            }
+        }
+    }
+}
+
+
+pub enum SettingsCommand {
+    /// Output config to use from the next boot, for changes the controller can't apply
+    /// until then
+    SaveOutput(OutputConfig),
+    /// Writes any pending changes now, e.g. before restarting
+    Flush,
+    /// Overwrites everything saved straight away, for a config that is applied by restarting.
+    /// The result of the write is sent back once it is done
+    Replace(Box<DeviceConfig>, mpsc::Sender<Result<()>>),
+}
+
+
+/// Saves what the controller and realtime listeners are running whenever it changes, once it has settled
+pub struct SettingsService {
+    _handle: thread::JoinHandle<()>,
+    pub settings_tx: mpsc::Sender<SettingsCommand>,
+}
+
+
+impl SettingsService {
+    /// `settings` and `map` are what was restored at boot
+    pub fn init(
+        store: SettingsStore,
+        settings: Settings,
+        map: Option<PixelMap>,
+        led_ctrl_svc: &LEDControllerService,
+        realtime_svc: &RealtimeService,
+    ) -> Result<Self> {
+        let (settings_tx, settings_rx) = mpsc::channel::<SettingsCommand>();
+
+        let mut writer = SettingsWriter {
+            store,
+            saved: settings.clone(),
+            pending: settings,
+            saved_map: map.clone(),
+            pending_map: map,
+            changed_at: None,
+            settings_rx,
+            running: Running {
+                output: led_ctrl_svc.current_output().lock().expect(CONTROLLER_MUTEX_ERR).clone(),
+                segments: led_ctrl_svc.current_segments().lock().expect(CONTROLLER_MUTEX_ERR).clone(),
+                map: led_ctrl_svc.current_map().lock().expect(CONTROLLER_MUTEX_ERR).clone(),
+                library: led_ctrl_svc.current_library().lock().expect(CONTROLLER_MUTEX_ERR).clone(),
+                realtime: realtime_svc.current_config().lock().expect(REALTIME_MUTEX_ERR).clone(),
+            },
+            cur_output: led_ctrl_svc.current_output().clone(),
+            cur_segments: led_ctrl_svc.current_segments().clone(),
+            cur_map: led_ctrl_svc.current_map().clone(),
+            cur_library: led_ctrl_svc.current_library().clone(),
+            cur_realtime: realtime_svc.current_config().clone(),
+        };
+
+        let join_handle = thread::Builder::new()
+            .stack_size(8192)
+            .spawn(move || writer.run())?;
+
+        Ok(Self {
+            _handle: join_handle,
+            settings_tx,
+        })
+    }
+}
+
+
+/// What was running at the last poll
+struct Running {
+    output: OutputConfig,
+    segments: Vec<SegmentConfig>,
+    map: Option<PixelMap>,
+    library: PresetLibrary,
+    realtime: RealtimeConfig,
+}
+
+struct SettingsWriter {
+    store: SettingsStore,
+    saved: Settings,
+    pending: Settings,
+    saved_map: Option<PixelMap>,
+    pending_map: Option<PixelMap>,
+    /// When `pending` last changed, if it hasn't been written since
+    changed_at: Option<Instant>,
+    settings_rx: mpsc::Receiver<SettingsCommand>,
+    running: Running,
+    cur_output: Arc<Mutex<OutputConfig>>,
+    cur_segments: Arc<Mutex<Vec<SegmentConfig>>>,
+    cur_map: Arc<Mutex<Option<PixelMap>>>,
+    cur_library: Arc<Mutex<PresetLibrary>>,
+    cur_realtime: Arc<Mutex<RealtimeConfig>>,
+}
+
+impl SettingsWriter {
+    fn run(&mut self) {
+        loop {
+            let mut flush = false;
+            match self.settings_rx.recv_timeout(POLL_INTERVAL) {
+                Ok(SettingsCommand::SaveOutput(output)) => {
+                    self.pending.output = output;
+                    self.changed_at = Some(Instant::now());
+                },
+                Ok(SettingsCommand::Flush) => flush = true,
+                Ok(SettingsCommand::Replace(config, reply)) => {
+                    self.pending = config.settings;
+                    self.pending_map = config.map;
+                    // Nobody is waiting if the request has already timed out
+                    let _ = reply.send(self.write());
+                },
+                Err(mpsc::RecvTimeoutError::Timeout) => (),
+                Err(mpsc::RecvTimeoutError::Disconnected) => return,
+            }
+
+            self.poll();
+
+            let settled = self.changed_at.map_or(false, |changed_at| changed_at.elapsed() >= DEBOUNCE);
+            if settled || (flush && self.changed_at.is_some()) {
+                // Already logged, and tried again after the next change
+                let _ = self.write();
+            }
+        }
+    }
+
+    /// Picks up anything that has changed since the last poll
+    fn poll(&mut self) {
+        let output = self.cur_output.lock().expect(CONTROLLER_MUTEX_ERR).clone();
+        if output != self.running.output {
+            self.pending.output = output.clone();
+            self.running.output = output;
+            self.changed_at = Some(Instant::now());
+        }
+
+        let segments = self.cur_segments.lock().expect(CONTROLLER_MUTEX_ERR).clone();
+        if segments != self.running.segments {
+            self.pending.segments = segments.clone();
+            self.running.segments = segments;
+            self.changed_at = Some(Instant::now());
+        }
+
+        let library = self.cur_library.lock().expect(CONTROLLER_MUTEX_ERR).clone();
+        if library != self.running.library {
+            self.pending.library = library.clone();
+            self.running.library = library;
+            self.changed_at = Some(Instant::now());
+        }
+
+        let realtime = self.cur_realtime.lock().expect(REALTIME_MUTEX_ERR).clone();
+        if realtime != self.running.realtime {
+            self.pending.realtime = realtime.clone();
+            self.running.realtime = realtime;
+            self.changed_at = Some(Instant::now());
+        }
+
+        let map = self.cur_map.lock().expect(CONTROLLER_MUTEX_ERR).clone();
+        if map != self.running.map {
+            self.pending_map = map.clone();
+            self.running.map = map;
+            self.changed_at = Some(Instant::now());
+        }
+    }
+
+    /// Only touches flash for values that differ from what is saved. Failed writes are
+    /// retried after the next change
+    fn write(&mut self) -> Result<()> {
+        self.changed_at = None;
+        let mut result = Ok(());
+
+        if self.pending != self.saved {
+            match self.store.save(&self.pending) {
+                Ok(_) => {
+                    log::info!("Saved settings");
+                    self.saved = self.pending.clone();
+                },
+                Err(e) => {
+                    log::warn!("Unable to save settings: {e:?}");
+                    result = Err(e);
+                },
+            }
+        }
+
+        if self.pending_map != self.saved_map {
+            match self.store.save_map(self.pending_map.as_ref()) {
+                Ok(_) => {
+                    log::info!("Saved pixel map");
+                    self.saved_map = self.pending_map.clone();
+                },
+                Err(e) => {
+                    log::warn!("Unable to save pixel map: {e:?}");
+                    result = result.and(Err(e));
+                },
+            }
+        }
+
+        result
+    }
+}
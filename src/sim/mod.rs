@@ -0,0 +1,258 @@
+use crate::effects::EffectConfig;
+use crate::realtime::{RealtimeConfig, RealtimeService};
+use crate::led_control::{Color, MatrixConfig, Output, OutputConfig, PixelMap, PresetLibrary, Segment, SegmentConfig, LEDControllerService, ControllerCommand, REFRESH_RATE};
+
+use anyhow::{Result, Error};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+
+#[cfg(test)]
+mod snapshot;
+
+
+const USAGE: &str = "usage: led-controller [EFFECT] [PARAM=VALUE ...] [--leds N] [--matrix WxH] [--map PATH] [--seed N] [--realtime] [--frames N] [--png PATH] [--gif PATH] [--quiet]";
+
+const STRIP_WIDTH: usize = 75; // LEDs per row when drawing to the terminal
+const PIXEL_SIZE: usize = 4; // Size of each LED in PNG/GIF dumps
+const DEFAULT_FRAMES: usize = 250;
+
+
+/// Host-side stand-in for the RMT output. Draws each frame to the terminal using ANSI
+/// true-color escapes and forwards a copy so it can be dumped to an image
+pub struct SimOutput {
+    terminal: bool,
+    rows_drawn: usize,
+    frame_tx: mpsc::Sender<Vec<Color>>,
+}
+
+impl SimOutput {
+    pub fn new(terminal: bool, frame_tx: mpsc::Sender<Vec<Color>>) -> Self {
+        Self {
+            terminal,
+            rows_drawn: 0,
+            frame_tx,
+        }
+    }
+
+    fn draw(&mut self, colors: &[Color]) -> Result<()> {
+        let mut output = String::new();
+
+        // Move back up to redraw over the previous frame
+        if self.rows_drawn > 0 {
+            output.push_str(&format!("\x1b[{}A", self.rows_drawn));
+        }
+
+        for row in colors.chunks(STRIP_WIDTH) {
+            for color in row {
+                output.push_str(&format!("\x1b[38;2;{};{};{}m\u{2588}", color.r, color.g, color.b));
+            }
+            output.push_str("\x1b[0m\n");
+        }
+        self.rows_drawn = (colors.len() + STRIP_WIDTH - 1) / STRIP_WIDTH;
+
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(output.as_bytes())?;
+        stdout.flush()?;
+
+        Ok(())
+    }
+}
+
+impl Output for SimOutput {
+    fn write(&mut self, frame: &Segment) -> Result<()> {
+        let colors: Vec<Color> = frame.leds().iter().map(|led| led.color().fold_white()).collect();
+
+        if self.terminal {
+            self.draw(&colors)?;
+        }
+
+        // Receiver hangs up once it has collected enough frames
+        let _ = self.frame_tx.send(colors);
+
+        Ok(())
+    }
+}
+
+
+struct SimArgs {
+    effect: EffectConfig,
+    leds: Option<usize>,
+    matrix: Option<MatrixConfig>,
+    map: Option<PathBuf>,
+    seed: Option<u64>,
+    realtime: bool,
+    frames: Option<usize>,
+    png: Option<PathBuf>,
+    gif: Option<PathBuf>,
+    terminal: bool,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<SimArgs> {
+    let mut effect = serde_json::Map::new();
+    let mut sim_args = SimArgs {
+        effect: EffectConfig::default(),
+        leds: None,
+        matrix: None,
+        map: None,
+        seed: None,
+        realtime: false,
+        frames: None,
+        png: None,
+        gif: None,
+        terminal: true,
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--leds" => sim_args.leds = Some(args.next().ok_or(Error::msg(USAGE))?.parse()?),
+            "--matrix" => {
+                let size = args.next().ok_or(Error::msg(USAGE))?;
+                let (width, height) = size.split_once('x').ok_or(Error::msg(USAGE))?;
+                sim_args.matrix = Some(MatrixConfig { width: width.parse()?, height: height.parse()?, ..Default::default() });
+            },
+            "--map" => sim_args.map = Some(args.next().ok_or(Error::msg(USAGE))?.into()),
+            "--seed" => sim_args.seed = Some(args.next().ok_or(Error::msg(USAGE))?.parse()?),
+            "--realtime" => sim_args.realtime = true,
+            "--frames" => sim_args.frames = Some(args.next().ok_or(Error::msg(USAGE))?.parse()?),
+            "--png" => sim_args.png = Some(args.next().ok_or(Error::msg(USAGE))?.into()),
+            "--gif" => sim_args.gif = Some(args.next().ok_or(Error::msg(USAGE))?.into()),
+            "--quiet" => sim_args.terminal = false,
+            "--help" | "-h" => return Err(Error::msg(USAGE)),
+            _ => match arg.split_once('=') {
+                Some((name, value)) => {
+                    // Accept anything JSON can parse, so numbers and bools keep their type
+                    let value = serde_json::from_str(value)
+                        .unwrap_or_else(|_| serde_json::Value::String(value.to_string()));
+                    effect.insert(name.to_string(), value);
+                },
+                None => {
+                    effect.insert("name".to_string(), serde_json::Value::String(arg));
+                },
+            }
+        }
+    }
+
+    if effect.contains_key("name") {
+        sim_args.effect = serde_json::from_value::<EffectConfig>(effect.into())?.resolve()?;
+    } else if !effect.is_empty() {
+        return Err(Error::msg("Parameters given without an effect name"));
+    }
+
+    Ok(sim_args)
+}
+
+
+/// JSON, or CSV if the file ends in `.csv`
+fn load_map(path: &Path) -> Result<PixelMap> {
+    let data = std::fs::read(path)?;
+    let map = if path.extension().map_or(false, |extension| extension == "csv") {
+        PixelMap::from_csv(std::str::from_utf8(&data)?)?
+    } else {
+        PixelMap::from_json(&data)?
+    };
+    map.validate()?;
+    Ok(map)
+}
+
+
+pub fn run() -> Result<()> {
+    let args = parse_args(std::env::args().skip(1))?;
+
+    let (frame_tx, frame_rx) = mpsc::channel();
+    let output = SimOutput::new(args.terminal, frame_tx);
+    let mut output_config = OutputConfig::default();
+    let map = args.map.as_deref().map(load_map).transpose()?;
+    // A matrix or map fills the strip unless told otherwise
+    let fill = args.matrix.map(|matrix| matrix.width * matrix.height).or(map.as_ref().map(PixelMap::len));
+    if let Some(leds) = args.leds.or(fill) {
+        output_config.strips[0].led_count = leds;
+    }
+    output_config.validate()?;
+
+    let led_ctrl_svc = LEDControllerService::init(
+        vec![Box::new(output)],
+        vec![SegmentConfig { effect: args.effect, matrix: args.matrix, ..Default::default() }],
+        output_config,
+        map,
+        PresetLibrary::default(),
+    )?;
+    if let Some(seed) = args.seed {
+        led_ctrl_svc.controller_tx.send(ControllerCommand::Reseed(seed))?;
+    }
+    // Listens on the usual ports so a packet generator can drive the simulated strip
+    let _realtime_svc = args.realtime
+        .then(|| RealtimeService::init(RealtimeConfig::default(), &led_ctrl_svc))
+        .transpose()?;
+
+    let recording = args.png.is_some() || args.gif.is_some();
+    let frame_count = match args.frames {
+        Some(frames) => frames,
+        None if recording => DEFAULT_FRAMES,
+        None => {
+            // Run until interrupted
+            for _ in frame_rx.iter() {}
+            return Ok(())
+        }
+    };
+
+    let frames: Vec<Vec<Color>> = frame_rx.iter().take(frame_count).collect();
+
+    if let Some(path) = &args.png {
+        save_png(path, &frames)?;
+        log::info!("Saved {} frames to {}", frames.len(), path.display());
+    }
+    if let Some(path) = &args.gif {
+        save_gif(path, &frames)?;
+        log::info!("Saved {} frames to {}", frames.len(), path.display());
+    }
+
+    Ok(())
+}
+
+
+/// Saves the frames as a timeline, one row per frame
+fn save_png(path: &Path, frames: &[Vec<Color>]) -> Result<()> {
+    let width = frames.first().map(|frame| frame.len()).unwrap_or_default() * PIXEL_SIZE;
+
+    let mut data = Vec::with_capacity(width * frames.len() * 3);
+    for frame in frames {
+        data.extend(scaled_row(frame));
+    }
+
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width as u32, frames.len() as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&data)?;
+
+    Ok(())
+}
+
+
+/// Saves the frames as an animated strip played back at the controller's refresh rate
+fn save_gif(path: &Path, frames: &[Vec<Color>]) -> Result<()> {
+    let width = frames.first().map(|frame| frame.len()).unwrap_or_default() * PIXEL_SIZE;
+    let height = PIXEL_SIZE;
+
+    let mut encoder = gif::Encoder::new(BufWriter::new(File::create(path)?), width as u16, height as u16, &[])?;
+    encoder.set_repeat(gif::Repeat::Infinite)?;
+
+    for frame in frames {
+        let row = scaled_row(frame);
+        let data = row.repeat(height);
+
+        let mut gif_frame = gif::Frame::from_rgb_speed(width as u16, height as u16, &data, 10);
+        gif_frame.delay = (REFRESH_RATE.as_millis() / 10) as u16; // In units of 10 ms
+        encoder.write_frame(&gif_frame)?;
+    }
+
+    Ok(())
+}
+
+
+fn scaled_row(frame: &[Color]) -> Vec<u8> {
+    frame.iter()
+        .flat_map(|color| [color.r, color.g, color.b].repeat(PIXEL_SIZE))
+        .collect()
+}
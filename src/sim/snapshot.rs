@@ -0,0 +1,153 @@
+use crate::effects::{EffectConfig, EffectRng, FrameContext, Layout, Params, EFFECTS};
+use crate::led_control::{CaptureOutput, Chipset, Color, Output, PixelMap, Segment, StripConfig, REFRESH_RATE};
+
+use anyhow::Result;
+use rand::SeedableRng;
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+
+const SNAPSHOT_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/snapshots");
+/// Set to rewrite the snapshots instead of checking them
+const BLESS_VAR: &str = "BLESS_SNAPSHOTS";
+
+const LED_COUNT: usize = 32;
+const MATRIX: Layout = Layout { width: 8, height: 4 }; // Same number of pixels as the strip
+const SEED: u64 = 0x5eed;
+const DURATION: Duration = Duration::from_secs(60); // Simulated time per effect
+const SAMPLE_EVERY: usize = 25; // Only record every 25th frame (0.5 s) to keep the files small
+
+
+/// Every registered effect with its default parameters, run on a simulated clock and a
+/// seeded rng
+#[test]
+fn effects_match_snapshots() -> Result<()> {
+    let mut failures = vec![];
+
+    for info in EFFECTS {
+        let config = EffectConfig {
+            id: info.id.to_string(),
+            params: info.resolve(&Params::default())?,
+        };
+        if !check(info.id, &render(&config)?)? {
+            failures.push(info.id);
+        }
+    }
+
+    assert!(failures.is_empty(), "Snapshots differ for {failures:?}. Re-run with {BLESS_VAR}=1 if this is expected");
+    Ok(())
+}
+
+/// The wire encoding of every chipset
+#[test]
+fn chipsets_match_snapshots() -> Result<()> {
+    assert!(check("chipsets", &render_chipsets()?)?, "Chipset snapshot differs. Re-run with {BLESS_VAR}=1 if this is expected");
+    Ok(())
+}
+
+
+/// Compares against or blesses the snapshot called `name`, returning whether it matched
+fn check(name: &str, rendered: &str) -> Result<bool> {
+    let path = PathBuf::from(SNAPSHOT_DIR).join(format!("{name}.snap"));
+
+    if std::env::var_os(BLESS_VAR).is_some() {
+        std::fs::create_dir_all(SNAPSHOT_DIR)?;
+        std::fs::write(&path, rendered)?;
+        println!("{name}: blessed {}", path.display());
+        return Ok(true)
+    }
+
+    match std::fs::read_to_string(&path) {
+        Ok(expected) if expected == rendered => {
+            println!("{name}: ok");
+            Ok(true)
+        },
+        Ok(expected) => {
+            let line = expected.lines().zip(rendered.lines())
+                .position(|(expected, rendered)| expected != rendered)
+                .unwrap_or_else(|| expected.lines().count().min(rendered.lines().count()));
+            println!("{name}: differs from {} at line {}", path.display(), line + 1);
+            Ok(false)
+        },
+        Err(e) => {
+            println!("{name}: unable to read {}: {e}", path.display());
+            Ok(false)
+        },
+    }
+}
+
+
+/// Renders the bytes a short test frame is sent as for every chipset, one line each
+fn render_chipsets() -> Result<String> {
+    let colors = [
+        Color::rgb(0x12, 0x34, 0x56),
+        Color::rgbw(0xff, 0x80, 0x00, 0x40),
+        Color::rgb(0x01, 0x00, 0x80),
+    ];
+    let mut frame = Segment::new(colors.len());
+    for (led, color) in frame.leds_mut().iter_mut().zip(colors) {
+        led.set(color);
+    }
+
+    let mut output = format!("# {colors:?}\n");
+
+    for chipset in Chipset::ALL {
+        let strip = StripConfig { chipset, ..Default::default() };
+        let mut capture = CaptureOutput::new(strip);
+        capture.write(&frame)?;
+
+        write!(output, "{}", serde_json::to_string(&chipset)?)?;
+        for data in capture.take_frames() {
+            for byte in data {
+                write!(output, " {byte:02x}")?;
+            }
+        }
+        output.push('\n');
+    }
+
+    Ok(output)
+}
+
+
+/// Renders sampled frames as one line each: the frame number followed by every LED as hex RGB
+fn render(config: &EffectConfig) -> Result<String> {
+    let (layout, size) = if config.info()?.factory.is_matrix() {
+        (MATRIX, format!("size={}x{}", MATRIX.width, MATRIX.height))
+    } else {
+        (Layout::strip(LED_COUNT), format!("leds={LED_COUNT}"))
+    };
+    let mut effect = config.build(layout, &PixelMap::grid(layout.width, layout.height))?;
+    let mut segment = Segment::new(layout.led_count());
+    let mut rng = EffectRng::seed_from_u64(SEED);
+
+    let mut output = format!(
+        "# {} seed={SEED:#x} {size} frame_ms={}\n",
+        serde_json::to_string(config)?,
+        REFRESH_RATE.as_millis(),
+    );
+
+    let frames = (DURATION.as_millis() / REFRESH_RATE.as_millis()) as u32;
+    for frame in 0..frames {
+        let elapsed = REFRESH_RATE * (frame + 1);
+        let mut ctx = FrameContext {
+            frame: frame as u64,
+            delta: REFRESH_RATE,
+            elapsed,
+            time: SystemTime::UNIX_EPOCH + elapsed,
+            rng: &mut rng,
+        };
+        effect.tick(&mut ctx, &mut segment)?;
+
+        if frame as usize % SAMPLE_EVERY == 0 {
+            write!(output, "{frame:05}")?;
+            for led in segment.leds() {
+                let color = led.color();
+                write!(output, " {:02x}{:02x}{:02x}", color.r, color.g, color.b)?;
+            }
+            output.push('\n');
+        }
+    }
+
+    Ok(output)
+}
@@ -0,0 +1,393 @@
+use anyhow::{Result, Error};
+use serde::{Serialize, Deserialize};
+
+use crate::effects::{EffectConfig, Params, EFFECTS};
+use crate::led_control::{
+    ControllerCommand, OutputConfig, PlaylistStatus, PowerStatus, PresetLibrary, RealtimeSource,
+    SegmentConfig, TransitionConfig, MAX_SEGMENTS, REFRESH_RATE,
+};
+use crate::realtime::WLED_PORT;
+
+
+/// WLED release whose JSON API this mirrors
+pub const API_VERSION: &str = "0.14.0";
+/// WLED apps only talk to devices reporting this brand
+const BRAND: &str = "WLED";
+const PRODUCT: &str = "LED Controller";
+/// WLED counts transitions in tenths of a second
+const TRANSITION_UNIT_MS: u64 = 100;
+/// WLED keeps transitions in 16 bits
+const MAX_TRANSITION_MS: u64 = u16::MAX as u64 * TRANSITION_UNIT_MS;
+
+
+/// `/json/state`, built from what the controller is running. Presets and playlists are
+/// numbered from 1 in the order they were saved
+#[derive(Serialize)]
+pub struct State {
+    pub on: bool,
+    pub bri: u8,
+    pub transition: u64,
+    pub ps: i32,
+    pub pl: i32,
+    pub mainseg: usize,
+    pub seg: Vec<SegmentState>,
+}
+
+#[derive(Serialize)]
+pub struct SegmentState {
+    pub id: usize,
+    pub start: usize,
+    pub stop: usize,
+    pub len: usize,
+    pub grp: usize,
+    pub spc: usize,
+    pub on: bool,
+    pub bri: u8,
+    /// Effects don't take colors, so these are always black
+    pub col: [[u8; 3]; 3],
+    pub fx: usize,
+    pub sx: u8,
+    pub ix: u8,
+    pub pal: usize,
+    pub sel: bool,
+    pub rev: bool,
+    pub mi: bool,
+    pub n: String,
+}
+
+impl State {
+    /// `last_brightness` is reported while the strips are off, as WLED keeps it separately.
+    /// `preset` is the name of the one last applied
+    pub fn new(
+        output: &OutputConfig,
+        segments: &[SegmentConfig],
+        library: &PresetLibrary,
+        preset: Option<&str>,
+        playlist: Option<&PlaylistStatus>,
+        last_brightness: u8,
+    ) -> Self {
+        let led_count = output.led_count();
+        let on = output.brightness > 0;
+
+        Self {
+            on,
+            bri: if on { output.brightness } else { last_brightness },
+            transition: TransitionConfig::default().duration_ms / TRANSITION_UNIT_MS,
+            ps: preset
+                .and_then(|name| library.presets.iter().position(|preset| preset.name == name))
+                .map_or(-1, |index| index as i32 + 1),
+            pl: playlist
+                .and_then(|status| library.playlists.iter().position(|playlist| playlist.name == status.playlist))
+                .map_or(-1, |index| index as i32 + 1),
+            mainseg: 0,
+            seg: segments.iter().enumerate().map(|(id, segment)| {
+                let len = segment.physical_length(led_count);
+                SegmentState {
+                    id,
+                    start: segment.start,
+                    stop: segment.start + len,
+                    len,
+                    grp: segment.grouping,
+                    spc: segment.spacing,
+                    on: segment.brightness > 0,
+                    bri: segment.brightness,
+                    col: [[0; 3]; 3],
+                    fx: effect_index(&segment.effect),
+                    sx: 128,
+                    ix: 128,
+                    pal: 0,
+                    sel: id == 0,
+                    rev: segment.reversed,
+                    mi: segment.mirrored,
+                    n: segment.name.clone(),
+                }
+            }).collect(),
+        }
+    }
+}
+
+
+/// On/off, or `"t"` to toggle
+#[derive(Clone, Copy, Deserialize)]
+#[serde(untagged)]
+pub enum Switch {
+    Set(bool),
+    Toggle(Toggle),
+}
+
+#[derive(Clone, Copy, Deserialize)]
+pub enum Toggle {
+    #[serde(rename = "t")]
+    Toggle,
+}
+
+impl Switch {
+    fn apply(self, current: bool) -> bool {
+        match self {
+            Switch::Set(on) => on,
+            Switch::Toggle(_) => !current,
+        }
+    }
+}
+
+
+/// Body of a `POST /json/state`. Anything not listed here is ignored
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct StateUpdate {
+    pub on: Option<Switch>,
+    pub bri: Option<u8>,
+    pub transition: Option<u64>,
+    pub ps: Option<i32>,
+    pub pl: Option<i32>,
+    pub seg: Vec<SegmentUpdate>,
+    /// Asks for the new state in the response
+    pub v: bool,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct SegmentUpdate {
+    /// Position in the list of updates when not given
+    pub id: Option<usize>,
+    pub on: Option<Switch>,
+    pub bri: Option<u8>,
+    pub fx: Option<usize>,
+    pub rev: Option<bool>,
+    pub mi: Option<bool>,
+    pub grp: Option<usize>,
+    pub spc: Option<usize>,
+    pub n: Option<String>,
+}
+
+impl StateUpdate {
+    /// Commands that carry out the update, checked in full before any are sent, and the state
+    /// once they have been applied. Turning the strips back on restores `last_brightness`
+    pub fn apply(
+        self,
+        output: &OutputConfig,
+        segments: &[SegmentConfig],
+        library: &PresetLibrary,
+        preset: Option<&str>,
+        playlist: Option<&PlaylistStatus>,
+        last_brightness: u8,
+    ) -> Result<(Vec<ControllerCommand>, State)> {
+        let mut commands = Vec::new();
+        let transition = match self.transition {
+            Some(transition) => TransitionConfig {
+                duration_ms: transition.saturating_mul(TRANSITION_UNIT_MS).min(MAX_TRANSITION_MS),
+                ..Default::default()
+            },
+            None => TransitionConfig::default(),
+        };
+
+        let on = self.on.map_or(output.brightness > 0, |on| on.apply(output.brightness > 0));
+        let brightness = match (on, self.bri) {
+            (false, _) => 0,
+            (true, Some(bri)) => bri,
+            (true, None) if output.brightness == 0 => last_brightness,
+            (true, None) => output.brightness,
+        };
+        let updated_output = OutputConfig { brightness, ..output.clone() };
+        if brightness != output.brightness {
+            commands.push(ControllerCommand::SetOutput(updated_output.clone(), None));
+        }
+
+        let mut updated = segments.to_vec();
+        for (position, update) in self.seg.into_iter().enumerate() {
+            let id = update.id.unwrap_or(position);
+            let segment = updated.get_mut(id).ok_or_else(|| Error::msg(format!("No segment {id}")))?;
+            update.apply(segment)?;
+        }
+        SegmentConfig::validate_all(&updated, output.led_count())?;
+
+        // Effect changes on their own crossfade, anything else rebuilds the segments
+        let only_effects = updated.iter().zip(segments)
+            .all(|(new, old)| SegmentConfig { effect: old.effect.clone(), ..new.clone() } == *old);
+        if only_effects {
+            for (index, (new, old)) in updated.iter().zip(segments).enumerate() {
+                if new.effect != old.effect {
+                    commands.push(ControllerCommand::SetEffect(index, new.effect.clone(), transition));
+                }
+            }
+        } else {
+            commands.push(ControllerCommand::SetSegments(updated.clone()));
+        }
+
+        match self.ps {
+            Some(id) if id > 0 => {
+                let preset = library.presets.get(id as usize - 1).ok_or_else(|| Error::msg(format!("No preset {id}")))?;
+                commands.push(ControllerCommand::ApplyPreset(preset.name.clone(), transition));
+            },
+            _ => (),
+        }
+        match self.pl {
+            Some(id) if id > 0 => {
+                let playlist = library.playlists.get(id as usize - 1).ok_or_else(|| Error::msg(format!("No playlist {id}")))?;
+                commands.push(ControllerCommand::PlayPlaylist(playlist.name.clone()));
+            },
+            Some(_) => commands.push(ControllerCommand::StopPlaylist),
+            None => (),
+        }
+
+        // Presets and playlists are left to the controller, so don't show up until it has
+        // switched to them
+        let state = State::new(&updated_output, &updated, library, preset, playlist, last_brightness);
+        Ok((commands, state))
+    }
+}
+
+impl SegmentUpdate {
+    fn apply(self, segment: &mut SegmentConfig) -> Result<()> {
+        if let Some(fx) = self.fx.filter(|fx| *fx != effect_index(&segment.effect)) {
+            let info = EFFECTS.get(fx).ok_or_else(|| Error::msg(format!("No effect {fx}")))?;
+            segment.effect = EffectConfig {
+                id: info.id.to_string(),
+                params: info.resolve(&Params::default())?,
+            };
+        }
+
+        // Like the strips, a segment turned back on comes back at full brightness unless told
+        // otherwise, since there is nowhere to keep what it was before
+        let on = self.on.map_or(segment.brightness > 0, |on| on.apply(segment.brightness > 0));
+        segment.brightness = match (on, self.bri) {
+            (false, _) => 0,
+            (true, Some(bri)) => bri,
+            (true, None) if segment.brightness == 0 => 255,
+            (true, None) => segment.brightness,
+        };
+
+        segment.reversed = self.rev.unwrap_or(segment.reversed);
+        segment.mirrored = self.mi.unwrap_or(segment.mirrored);
+        segment.grouping = self.grp.unwrap_or(segment.grouping);
+        segment.spacing = self.spc.unwrap_or(segment.spacing);
+        if let Some(name) = self.n {
+            segment.name = name;
+        }
+        Ok(())
+    }
+}
+
+
+/// `/json/info`. `uptime` is in seconds
+#[derive(Serialize)]
+pub struct Info {
+    pub ver: &'static str,
+    pub leds: LedInfo,
+    pub name: &'static str,
+    pub udpport: u16,
+    pub live: bool,
+    pub lm: String,
+    pub fxcount: usize,
+    pub palcount: usize,
+    pub arch: &'static str,
+    pub brand: &'static str,
+    pub product: &'static str,
+    pub uptime: u64,
+    pub freeheap: u32,
+    /// Lowercase hex without separators
+    pub mac: String,
+}
+
+#[derive(Serialize)]
+pub struct LedInfo {
+    pub count: usize,
+    pub rgbw: bool,
+    pub fps: u32,
+    /// Estimated draw in milliamps
+    pub pwr: u32,
+    pub maxpwr: u32,
+    pub maxseg: usize,
+}
+
+impl Info {
+    pub fn new(
+        output: &OutputConfig,
+        power: &PowerStatus,
+        live: Option<RealtimeSource>,
+        uptime: u64,
+        freeheap: u32,
+        mac: [u8; 6],
+    ) -> Self {
+        Self {
+            ver: API_VERSION,
+            leds: LedInfo {
+                count: output.led_count(),
+                rgbw: output.strips.iter().any(|strip| strip.chipset.channels() == 4),
+                fps: (1000 / REFRESH_RATE.as_millis()) as u32,
+                pwr: power.estimated_milliamps,
+                maxpwr: output.power.max_milliamps,
+                maxseg: MAX_SEGMENTS,
+            },
+            name: PRODUCT,
+            udpport: WLED_PORT,
+            live: live.is_some(),
+            lm: live.map(|source| format!("{source:?}")).unwrap_or_default(),
+            fxcount: EFFECTS.len(),
+            palcount: 1,
+            arch: "esp32",
+            brand: BRAND,
+            product: PRODUCT,
+            uptime,
+            freeheap,
+            mac: mac.iter().map(|byte| format!("{byte:02x}")).collect(),
+        }
+    }
+}
+
+
+/// `/json`, everything the separate endpoints return at once
+#[derive(Serialize)]
+pub struct Everything {
+    pub state: State,
+    pub info: Info,
+    pub effects: Vec<&'static str>,
+    pub palettes: Vec<&'static str>,
+}
+
+
+/// `/json/effects`, in the order `fx` indexes them
+pub fn effect_names() -> Vec<&'static str> {
+    EFFECTS.iter().map(|info| info.name).collect()
+}
+
+/// `palettes` in `/json`. Effects pick their own colors, so there is only the one
+pub fn palette_names() -> Vec<&'static str> {
+    vec!["Default"]
+}
+
+fn effect_index(effect: &EffectConfig) -> usize {
+    EFFECTS.iter().position(|info| info.id == effect.id).unwrap_or_default()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_and_applies_presets() -> Result<()> {
+        let output = OutputConfig::default();
+        let segments = vec![SegmentConfig::default()];
+        let library: PresetLibrary = serde_json::from_str(r#"{"presets": [{"name": "calm", "segments": [], "brightness": 40}]}"#)?;
+
+        assert_eq!(State::new(&output, &segments, &library, Some("calm"), None, 255).ps, 1);
+        assert_eq!(State::new(&output, &segments, &library, Some("gone"), None, 255).ps, -1);
+        assert_eq!(State::new(&output, &segments, &library, None, None, 255).ps, -1);
+
+        // Transitions far beyond what WLED can send are clamped rather than overflowing
+        let update: StateUpdate = serde_json::from_str(&format!(r#"{{"ps": 1, "transition": {}}}"#, u64::MAX))?;
+        let (commands, _) = update.apply(&output, &segments, &library, None, None, 255)?;
+        assert!(matches!(
+            commands.as_slice(),
+            [ControllerCommand::ApplyPreset(name, transition)] if name == "calm" && transition.duration_ms == MAX_TRANSITION_MS
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn formats_the_mac() {
+        let info = Info::new(&OutputConfig::default(), &PowerStatus::default(), None, 0, 0, [0x24, 0x6f, 0x28, 0xa, 0xbc, 0xde]);
+        assert_eq!(info.mac, "246f280abcde");
+    }
+}